@@ -6,9 +6,29 @@ use termion::input::TermRead;
 use termion::raw::IntoRawMode;
 use termion::{color, style};
 
-use radioklw::RadioStation;
+use radioklw::{Event, RadioStation, StationOrder, TimeshiftAction, DEFAULT_ZONE};
 
-use crate::rclient::Rclient;
+use crate::rclient::{self, Rclient};
+
+// Jumps of the arrow keys and Ctrl-b within the timeshift buffer.
+const SHORT_JUMP_SECS: u32 = 30;
+const LONG_JUMP_SECS: u32 = 5 * 60;
+
+// The level meter in the header spans this range of dBFS.
+const METER_WIDTH: usize = 10;
+const METER_FLOOR_DB: f32 = -60.0;
+
+// Sort keys Ctrl-o cycles through.
+const SORT_KEYS: [StationOrder; 8] = [
+    StationOrder::Clickcount,
+    StationOrder::Votes,
+    StationOrder::Clicktrend,
+    StationOrder::Bitrate,
+    StationOrder::Name,
+    StationOrder::Country,
+    StationOrder::Language,
+    StationOrder::Codec,
+];
 
 #[derive(Debug)]
 struct Coordinates {
@@ -23,6 +43,14 @@ pub struct StationViewer {
     terminal_size: Coordinates,
     favorites_menu: bool,
     curr_playing: Option<usize>,
+    play_info: String,
+    live_offset: String,
+    paused: bool,
+    level: Option<f32>,
+    order: Option<StationOrder>,
+    reverse: bool,
+    /// Outcome of the last action, shown until the next key
+    notice: String,
     rclient: Rclient,
 }
 
@@ -43,6 +71,13 @@ impl StationViewer {
             },
             favorites_menu: show_favorites,
             curr_playing: None,
+            play_info: String::new(),
+            live_offset: String::new(),
+            paused: false,
+            level: None,
+            order: None,
+            reverse: true,
+            notice: String::new(),
             rclient,
         }
     }
@@ -50,50 +85,78 @@ impl StationViewer {
     async fn show_list(&mut self, is_action: bool) {
         let ill_char = '\n';
         let mut footer_str =
-            ">>> Options: Ctrl-p to Play, Ctrl-s to Stop, Ctrl-w to Save, Ctrl-q to Quit";
+            ">>> Options: Ctrl-p to Play, Ctrl-s to Stop, Ctrl-t to Pause, Left/Right to Seek, Ctrl-o/Ctrl-r to Sort, Ctrl-v to Vote, Ctrl-w to Save, Ctrl-q to Quit";
 
         print!("{}{}", termion::clear::All, termion::cursor::Goto(1, 1));
-        let playing_now = if let Some(cpl) = self.curr_playing {
+        let meter = level_meter(self.level);
+        let mut playing_now = if let Some(cpl) = self.curr_playing {
             self.radio_list[cpl].name.replace(ill_char, "")
         } else {
             "-".to_string()
         };
+        if !self.play_info.is_empty() {
+            playing_now = format!("{} ({})", playing_now, self.play_info);
+        }
+        if !self.live_offset.is_empty() {
+            playing_now = format!("{} [{}]", playing_now, self.live_offset);
+        }
+        if !self.notice.is_empty() {
+            playing_now = format!("{} -- {}", playing_now, self.notice);
+        }
+        let sorted_by = match self.order {
+            Some(order) if self.reverse => format!(", by {} descending", order),
+            Some(order) => format!(", by {}", order),
+            None if self
+                .radio_list
+                .first()
+                .is_some_and(|station| station.distance_km.is_some()) =>
+            {
+                ", by distance".to_string()
+            }
+            None => String::new(),
+        };
 
         if self.favorites_menu {
             footer_str =
-                ">>> Options: Ctrl-p to Play, Ctrl-s to Stop, Ctrl-d to Delete, Ctrl-q to Quit";
+                ">>> Options: Ctrl-p to Play, Ctrl-s to Stop, Ctrl-t to Pause, Left/Right to Seek, Ctrl-o/Ctrl-r to Sort, Ctrl-v to Vote, Ctrl-d to Delete, Ctrl-q to Quit";
             if is_action {
                 println!(
-                    "{}{}*** {} <<<< DELETED >>>>\r{}",
+                    "{}{}{} *** {} <<<< DELETED >>>>\r{}",
                     color::Bg(color::Black),
                     color::Fg(color::LightCyan),
+                    meter,
                     self.radio_list[self.cur_pos.y - 1].name,
                     style::Reset
                 );
             } else {
                 println!(
-                    "{}{}({} stations found) Playing now: {}\r{}",
+                    "{}{}{} ({} stations found{}) Playing now: {}\r{}",
                     color::Bg(color::Black),
                     color::Fg(color::LightGreen),
+                    meter,
                     self.radio_list_len,
+                    sorted_by,
                     playing_now,
                     style::Reset
                 );
             }
         } else if is_action {
             println!(
-                "{}{}*** {} <<<< SAVED >>>>\r{}",
+                "{}{}{} *** {} <<<< SAVED >>>>\r{}",
                 color::Bg(color::Black),
                 color::Fg(color::LightCyan),
+                meter,
                 self.radio_list[self.cur_pos.y - 1].name,
                 style::Reset
             );
         } else {
             println!(
-                "{}{}({} stations found) Playing now: {}\r{}",
+                "{}{}{} ({} stations found{}) Playing now: {}\r{}",
                 color::Bg(color::Black),
                 color::Fg(color::LightGreen),
+                meter,
                 self.radio_list_len,
+                sorted_by,
                 playing_now,
                 style::Reset
             );
@@ -103,37 +166,19 @@ impl StationViewer {
 
         if self.radio_list_len < self.terminal_size.y {
             for line in 0..self.radio_list_len {
-                println!(
-                    "{}: {}, Country: {}, Language: {}\r",
-                    line + 1,
-                    self.radio_list[line].name,
-                    self.radio_list[line].country,
-                    self.radio_list[line].language,
-                );
+                println!("{}\r", row(line, &self.radio_list[line]));
             }
             show_cursor = true
         } else if self.cur_pos.y < self.terminal_size.y
             && self.cur_pos.y <= (self.terminal_size.y - 3)
         {
             for line in 0..self.terminal_size.y - 3 {
-                println!(
-                    "{}: {}, Country: {}, Language: {}\r",
-                    line + 1,
-                    self.radio_list[line].name,
-                    self.radio_list[line].country,
-                    self.radio_list[line].language
-                );
+                println!("{}\r", row(line, &self.radio_list[line]));
             }
             show_cursor = true
         } else {
             for line in self.cur_pos.y - (self.terminal_size.y - 3)..self.cur_pos.y {
-                println!(
-                    "{}: {}, Country: {}, Language: {}\r",
-                    line + 1,
-                    self.radio_list[line].name,
-                    self.radio_list[line].country,
-                    self.radio_list[line].language
-                );
+                println!("{}\r", row(line, &self.radio_list[line]));
             }
         }
 
@@ -191,6 +236,106 @@ impl StationViewer {
         );
     }
 
+    async fn timeshift(&mut self, action: TimeshiftAction) -> RadioResult<()> {
+        if self.curr_playing.is_none() {
+            return Ok(());
+        }
+
+        self.rclient.send_timeshift_message(action).await?;
+        match self.rclient.recv_status().await {
+            Ok(status) => {
+                self.live_offset = rclient::live_offset(&status);
+                self.paused = status.paused;
+            }
+            Err(err) => self.live_offset = err.to_string(),
+        }
+        self.show_list(false).await;
+        Ok(())
+    }
+
+    /// Sorts the list by the chosen key, the playing station stays marked.
+    async fn sort(&mut self) {
+        let Some(order) = self.order else {
+            return;
+        };
+
+        let playing = self
+            .curr_playing
+            .map(|curr| self.radio_list[curr].stationuuid.clone());
+        let mut radio_list = (*self.radio_list).clone();
+        radio_list.sort_by(|a, b| {
+            let ordering = order.compare(a, b);
+            if self.reverse {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        self.curr_playing = playing.and_then(|uuid| {
+            radio_list
+                .iter()
+                .position(|station| station.stationuuid == uuid)
+        });
+        self.radio_list = Arc::new(radio_list);
+        self.show_list(false).await;
+    }
+
+    async fn play(&mut self, selected: usize) -> RadioResult<()> {
+        self.rclient
+            .send_play_message(&self.radio_list[selected])
+            .await?;
+        match self.rclient.recv_status().await {
+            Ok(status) => {
+                self.curr_playing = Some(selected);
+                self.live_offset = rclient::live_offset(&status);
+                self.paused = status.paused;
+                self.play_info = status.variant.unwrap_or_default();
+            }
+            Err(err) => {
+                self.curr_playing = None;
+                self.level = None;
+                self.live_offset.clear();
+                self.play_info = err.to_string();
+            }
+        }
+        self.show_list(false).await;
+        Ok(())
+    }
+
+    /// Redraws only the level meter at the start of the header.
+    fn show_level(&self) {
+        print!(
+            "{}{}{}{}{}{}{}",
+            termion::cursor::Save,
+            termion::cursor::Goto(1, 1),
+            color::Bg(color::Black),
+            color::Fg(color::LightGreen),
+            level_meter(self.level),
+            style::Reset,
+            termion::cursor::Restore,
+        );
+    }
+
+    /// Updates the level meter of the playing station, and moves on to the
+    /// next station of the list when the playing one has gone silent.
+    async fn handle_event(&mut self, event: &Event) -> RadioResult<()> {
+        let zone = self.rclient.zone.as_deref().unwrap_or(DEFAULT_ZONE);
+        match (event, self.curr_playing) {
+            (Event::Level { zone: playing, db }, Some(_)) if playing == zone => {
+                self.level = Some(*db);
+                self.show_level();
+                Ok(())
+            }
+            (Event::Silence { zone: silent, .. }, Some(curr))
+                if silent == zone && self.rclient.skip_silent && self.radio_list_len > 1 =>
+            {
+                self.play((curr + 1) % self.radio_list_len).await
+            }
+            _ => Ok(()),
+        }
+    }
+
     async fn run(&mut self) -> RadioResult<()> {
         let mut stdout = stdout().into_raw_mode()?;
 
@@ -206,8 +351,26 @@ impl StationViewer {
             }
         });
 
-        // Process keys asynchronously
-        while let Some(key_result) = rx.recv().await {
+        // Without the events, e.g. from an older server, there is no level
+        // meter and no skipping of silent stations.
+        let (_events_tx, mut events) = tokio::sync::mpsc::unbounded_channel();
+        if let Ok(subscription) = self.rclient.subscribe().await {
+            events = subscription;
+        }
+
+        // Process keys and events asynchronously
+        loop {
+            let key_result = tokio::select! {
+                key_result = rx.recv() => match key_result {
+                    Some(key_result) => key_result,
+                    None => break,
+                },
+                Some(event) = events.recv() => {
+                    self.handle_event(&event).await?;
+                    stdout.flush()?;
+                    continue;
+                }
+            };
             let key = match key_result {
                 Ok(k) => k,
                 Err(e) => {
@@ -216,37 +379,76 @@ impl StationViewer {
                 }
             };
 
+            self.notice.clear();
             match key {
                 Key::Ctrl('q') => {
                     break;
                 }
                 Key::Ctrl('p') => {
-                    self.curr_playing = Some(self.cur_pos.y - 1);
-                    self.rclient
-                        .send_play_message(&self.radio_list[self.cur_pos.y - 1].url)
-                        .await?;
-                    self.show_list(false).await;
+                    self.play(self.cur_pos.y - 1).await?;
                 }
                 Key::Ctrl('s') => {
                     self.curr_playing = None;
+                    self.level = None;
+                    self.play_info.clear();
+                    self.live_offset.clear();
                     self.rclient.send_stop_message().await?;
                     self.show_list(false).await;
                 }
-                Key::Ctrl('w') => {
-                    if !self.favorites_menu {
-                        self.rclient
-                            .save_station_to_file(&self.radio_list[self.cur_pos.y - 1])
-                            .await?;
-                        self.show_list(true).await;
-                    }
+                Key::Ctrl('t') => {
+                    let action = if self.paused {
+                        TimeshiftAction::Resume
+                    } else {
+                        TimeshiftAction::Pause
+                    };
+                    self.timeshift(action).await?;
+                }
+                Key::Left => {
+                    self.timeshift(TimeshiftAction::Back(SHORT_JUMP_SECS))
+                        .await?;
+                }
+                Key::Right => {
+                    self.timeshift(TimeshiftAction::Forward(SHORT_JUMP_SECS))
+                        .await?;
+                }
+                Key::Ctrl('b') => {
+                    self.timeshift(TimeshiftAction::Back(LONG_JUMP_SECS))
+                        .await?;
+                }
+                Key::Ctrl('l') => {
+                    self.timeshift(TimeshiftAction::Live).await?;
+                }
+                Key::Ctrl('w') if !self.favorites_menu => {
+                    self.rclient
+                        .save_station_to_file(&self.radio_list[self.cur_pos.y - 1])
+                        .await?;
+                    self.show_list(true).await;
+                }
+                Key::Ctrl('v') => {
+                    let station = self.radio_list[self.cur_pos.y - 1].clone();
+                    self.notice = match self.rclient.vote(&station).await {
+                        Ok(station) => format!("voted for {}", station.name),
+                        Err(err) => err.to_string(),
+                    };
+                    self.show_list(false).await;
+                }
+                Key::Ctrl('o') => {
+                    let next = self
+                        .order
+                        .and_then(|order| SORT_KEYS.iter().position(|key| *key == order))
+                        .map_or(0, |index| (index + 1) % SORT_KEYS.len());
+                    self.order = Some(SORT_KEYS[next]);
+                    self.sort().await;
                 }
-                Key::Ctrl('d') => {
-                    if self.favorites_menu {
-                        self.rclient
-                            .delete_station_from_favorites(self.cur_pos.y - 1)
-                            .await?;
-                        self.show_list(true).await;
-                    }
+                Key::Ctrl('r') => {
+                    self.reverse = !self.reverse;
+                    self.order = self.order.or(Some(SORT_KEYS[0]));
+                    self.sort().await;
+                }
+                Key::Ctrl('d') if self.favorites_menu => {
+                    let station = self.radio_list[self.cur_pos.y - 1].clone();
+                    self.rclient.delete_station_from_favorites(&station).await?;
+                    self.show_list(true).await;
                 }
                 Key::Up => {
                     self.dec_y();
@@ -263,7 +465,9 @@ impl StationViewer {
         Ok(())
     }
 
-    pub async fn run_chooser(&mut self) -> RadioResult<Option<usize>> {
+    /// Shows the stations until the user quits and returns the one left
+    /// playing.
+    pub async fn run_chooser(&mut self) -> RadioResult<Option<RadioStation>> {
         println!("{}", termion::clear::All);
         println!("{}", termion::cursor::Show);
         println!("{}", termion::cursor::Goto(1, 1));
@@ -274,10 +478,49 @@ impl StationViewer {
             "{}",
             termion::cursor::Goto(1, (self.terminal_size.y + 3).try_into()?)
         );
-        if self.curr_playing.is_some() {
-            return Ok(self.curr_playing);
-        }
+        // The list may have been sorted, so the index is only valid here.
+        Ok(self.curr_playing.map(|curr| self.radio_list[curr].clone()))
+    }
+}
+
+/// A line of the station list, with the distance of a nearby search.
+fn row(line: usize, station: &RadioStation) -> String {
+    let row = format!(
+        "{}: {}, Country: {}, Language: {}",
+        line + 1,
+        station.name,
+        station.country,
+        station.language
+    );
+    match station.distance_km {
+        Some(distance) => format!("{}, Distance: {:.0} km", row, distance),
+        None => row,
+    }
+}
+
+/// Draws a dBFS level as a bar, empty when nothing is playing.
+fn level_meter(level: Option<f32>) -> String {
+    let filled = level.map_or(0, |db| {
+        let fraction = (db - METER_FLOOR_DB) / -METER_FLOOR_DB;
+        (fraction.clamp(0.0, 1.0) * METER_WIDTH as f32).round() as usize
+    });
+    format!(
+        "[{}{}]",
+        "|".repeat(filled),
+        " ".repeat(METER_WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        Ok(None)
+    #[test]
+    fn level_meter_scales_from_the_floor() {
+        assert_eq!(level_meter(None), "[          ]");
+        assert_eq!(level_meter(Some(0.0)), "[||||||||||]");
+        assert_eq!(level_meter(Some(-30.0)), "[|||||     ]");
+        assert_eq!(level_meter(Some(-90.0)), "[          ]");
+        assert_eq!(level_meter(Some(3.0)), "[||||||||||]");
     }
 }
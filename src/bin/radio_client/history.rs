@@ -0,0 +1,112 @@
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use radioklw::utils::RadioResult;
+use radioklw::HistoryEntry;
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+/// Start of the given local day.
+pub fn day_start(date: NaiveDate) -> RadioResult<DateTime<Utc>> {
+    local_to_utc(date.and_hms_opt(0, 0, 0))
+}
+
+/// End of the given local day.
+pub fn day_end(date: NaiveDate) -> RadioResult<DateTime<Utc>> {
+    local_to_utc(date.and_hms_opt(23, 59, 59))
+}
+
+fn local_to_utc(time: Option<chrono::NaiveDateTime>) -> RadioResult<DateTime<Utc>> {
+    time.and_then(|time| time.and_local_timezone(Local).earliest())
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(|| "invalid local time".into())
+}
+
+pub fn print_history(entries: &[HistoryEntry], format: OutputFormat) -> RadioResult<()> {
+    match format {
+        OutputFormat::Table => print_table(entries),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(entries)?),
+        OutputFormat::Csv => print_csv(entries),
+    }
+    Ok(())
+}
+
+fn local_time(time: &DateTime<Utc>) -> String {
+    time.with_timezone(&Local).format(TIME_FORMAT).to_string()
+}
+
+fn stopped_time(entry: &HistoryEntry) -> String {
+    entry
+        .stopped
+        .as_ref()
+        .map(local_time)
+        .unwrap_or_else(|| "playing".to_string())
+}
+
+fn print_table(entries: &[HistoryEntry]) {
+    if entries.is_empty() {
+        println!("No history found");
+        return;
+    }
+
+    println!(
+        "{:<16}  {:<16}  {:<10}  Station",
+        "Started", "Stopped", "Zone"
+    );
+    for entry in entries {
+        println!(
+            "{:<16}  {:<16}  {:<10}  {}",
+            local_time(&entry.started),
+            stopped_time(entry),
+            entry.zone,
+            entry.station
+        );
+        for track in &entry.tracks {
+            println!("    {}  {}", local_time(&track.started), track.title);
+        }
+    }
+}
+
+/// One row per track, sessions without tracks get a single row with an
+/// empty track.
+fn print_csv(entries: &[HistoryEntry]) {
+    println!("started,stopped,zone,station,stationuuid,track_started,track");
+    for entry in entries {
+        let session = [
+            entry.started.to_rfc3339(),
+            entry
+                .stopped
+                .map(|stopped| stopped.to_rfc3339())
+                .unwrap_or_default(),
+            csv_field(&entry.zone),
+            csv_field(&entry.station),
+            entry.stationuuid.clone(),
+        ]
+        .join(",");
+
+        if entry.tracks.is_empty() {
+            println!("{},,", session);
+        }
+        for track in &entry.tracks {
+            println!(
+                "{},{},{}",
+                session,
+                track.started.to_rfc3339(),
+                csv_field(&track.title)
+            );
+        }
+    }
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
@@ -1,8 +1,12 @@
 use clap::{Parser, Subcommand};
 use radioklw::utils::RadioResult;
+use radioklw::{gazetteer, AudioSettings, EqPreset, SearchData, StationOrder};
 
+use crate::history::OutputFormat;
 use crate::rclient::Rclient;
+use chrono::NaiveDate;
 mod chooser;
+mod history;
 mod rclient;
 mod server_initializer;
 
@@ -17,6 +21,15 @@ pub struct Args {
     #[clap(short, long)]
     language: Option<String>,
 
+    /// Playback zone to control (the server's default zone if not given)
+    #[clap(short, long)]
+    zone: Option<String>,
+
+    /// In the station list, play the next station when the current one
+    /// goes silent
+    #[clap(long)]
+    skip_silent: bool,
+
     /// Stream connection address
     #[clap(short, long, default_value_t = String::from("localhost:8080"))]
     addr: String,
@@ -31,21 +44,413 @@ pub enum Commands {
     Search {
         /// Name of the radio station to search
         name: Option<String>,
+
+        #[command(flatten)]
+        filters: SearchArgs,
+    },
+    /// Search the radio stations near a place, the nearest first
+    Near {
+        /// City, e.g. Berlin or "Valencia, ES", or coordinates as LAT,LONG
+        #[arg(allow_hyphen_values = true, value_parser = parse_place)]
+        place: (f64, f64),
+
+        /// Kilometers around the place
+        #[arg(long, default_value_t = 100.0)]
+        radius: f64,
+
+        #[command(flatten)]
+        filters: SearchArgs,
     },
     /// Get radio station from saved favorites
-    Favs,
+    Favs {
+        #[command(subcommand)]
+        action: Option<FavsAction>,
+    },
     /// Stop the radio station stream
     Stop,
+    /// Show the radio station currently playing
+    Status,
+    /// Vote for the radio station currently playing on radio-browser
+    Vote,
+    /// Set the playback volume (0-100)
+    Volume {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        volume: u8,
+    },
+    /// Set the audio output device (the server-wide device if not given)
+    Device {
+        /// Name of the output device as listed by the devices command
+        name: Option<String>,
+
+        /// Set the device of all zones without their own instead of the
+        /// zone's (the system default if no name is given)
+        #[arg(long)]
+        server: bool,
+    },
+    /// List the audio output devices of the server
+    Devices,
+    /// Pause the live stream, it can be resumed where it was paused
+    Pause,
+    /// Resume a paused stream where it was paused
+    Resume,
+    /// Jump back in the stream
+    Rewind {
+        /// Seconds to jump back
+        #[arg(default_value_t = 30)]
+        secs: u32,
+    },
+    /// Jump forward in a rewound stream
+    Forward {
+        /// Seconds to jump forward
+        #[arg(default_value_t = 30)]
+        secs: u32,
+    },
+    /// Return to the live stream
+    Live,
+    /// List the countries with their station counts
+    Countries {
+        #[command(flatten)]
+        facets: FacetArgs,
+    },
+    /// List the languages with their station counts
+    Languages {
+        #[command(flatten)]
+        facets: FacetArgs,
+    },
+    /// List the tags with their station counts
+    Tags {
+        #[command(flatten)]
+        facets: FacetArgs,
+    },
+    /// List the stream codecs with their station counts
+    Codecs {
+        #[command(flatten)]
+        facets: FacetArgs,
+    },
+    /// List the states and regions with their station counts
+    States {
+        #[command(flatten)]
+        facets: FacetArgs,
+    },
+    /// List the playback zones of the server
+    Zones,
+    /// Print the server's events, such as player errors, as they happen
+    Events,
+    /// Show the listening history
+    History {
+        /// First day to show (YYYY-MM-DD)
+        #[arg(long)]
+        from: Option<NaiveDate>,
+
+        /// Last day to show (YYYY-MM-DD)
+        #[arg(long)]
+        to: Option<NaiveDate>,
+
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    /// Show or change the server-wide audio processing
+    Audio {
+        #[command(flatten)]
+        settings: AudioArgs,
+    },
+    /// Show or clear the server's cache of searches and lists
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
     /// Stop the radio stream server
     StopServer,
 }
 
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum FavsAction {
+    /// Check that the saved favorites are still reachable
+    Check,
+    /// Show or change the audio processing of a saved favorite
+    Audio {
+        /// Number of the favorite as shown in the favorites list
+        number: usize,
+
+        #[command(flatten)]
+        settings: AudioArgs,
+
+        /// Use the server-wide audio processing for the favorite again
+        #[arg(long)]
+        reset: bool,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum CacheAction {
+    /// Show the size of the cache and how often it was used
+    Stats,
+    /// Remove all cached responses, the next requests reach the directory
+    Clear,
+}
+
+#[derive(clap::Args, Debug, PartialEq)]
+pub struct SearchArgs {
+    /// Stations with a tag containing this, e.g. jazz
+    #[arg(short, long)]
+    tag: Option<String>,
+
+    /// Stations with all of these comma separated tags
+    #[arg(long, value_delimiter = ',')]
+    tags: Vec<String>,
+
+    /// Two letter country code, e.g. DE
+    #[arg(long)]
+    countrycode: Option<String>,
+
+    /// State or region of the country
+    #[arg(long)]
+    state: Option<String>,
+
+    /// Stream codec, e.g. MP3, AAC or OGG
+    #[arg(long)]
+    codec: Option<String>,
+
+    /// Lowest bitrate in kbit/s
+    #[arg(long)]
+    bitrate_min: Option<u32>,
+
+    /// Highest bitrate in kbit/s
+    #[arg(long)]
+    bitrate_max: Option<u32>,
+
+    /// Only stations streaming over HTTPS
+    #[arg(long)]
+    https: bool,
+
+    /// Only stations with a location
+    #[arg(long)]
+    geo: bool,
+
+    /// Leave out the stations that failed the last check
+    #[arg(long)]
+    hide_broken: bool,
+
+    /// Match the name exactly
+    #[arg(long)]
+    name_exact: bool,
+
+    /// Match the country exactly
+    #[arg(long)]
+    country_exact: bool,
+
+    /// Match the language exactly
+    #[arg(long)]
+    language_exact: bool,
+
+    /// Match the tag exactly
+    #[arg(long)]
+    tag_exact: bool,
+
+    /// Match the state exactly
+    #[arg(long)]
+    state_exact: bool,
+
+    /// Number of stations to skip, to page through the results
+    #[arg(long)]
+    offset: Option<u32>,
+
+    /// Most stations to return
+    #[arg(long)]
+    limit: Option<u32>,
+
+    /// Sort key, e.g. name, votes, bitrate, clicktrend or random (the most
+    /// clicked stations first if not given)
+    #[arg(short, long)]
+    order: Option<StationOrder>,
+
+    /// Sort in descending order
+    #[arg(short, long)]
+    reverse: bool,
+}
+
+impl SearchArgs {
+    pub fn apply(&self, data: &mut SearchData) {
+        data.tag = self.tag.clone();
+        data.tag_list = self.tags.clone();
+        data.countrycode = self.countrycode.clone();
+        data.state = self.state.clone();
+        data.codec = self.codec.clone();
+        data.bitrate_min = self.bitrate_min;
+        data.bitrate_max = self.bitrate_max;
+        data.is_https = self.https.then_some(true);
+        data.has_geo_info = self.geo.then_some(true);
+        data.hidebroken = self.hide_broken;
+        data.name_exact = self.name_exact;
+        data.country_exact = self.country_exact;
+        data.language_exact = self.language_exact;
+        data.tag_exact = self.tag_exact;
+        data.state_exact = self.state_exact;
+        data.offset = self.offset;
+        data.limit = self.limit;
+        data.order = self.order;
+        data.reverse = self.reverse.then_some(true);
+    }
+}
+
+/// Reads coordinates, or looks the place up in the bundled list of cities.
+fn parse_place(place: &str) -> Result<(f64, f64), String> {
+    let coordinates = place
+        .split_once(',')
+        .and_then(|(lat, long)| Some((lat.trim().parse().ok()?, long.trim().parse().ok()?)));
+    match coordinates {
+        Some((lat, long)) if (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&long) => {
+            Ok((lat, long))
+        }
+        Some(_) => Err(format!("coordinates out of range: {}", place)),
+        None => gazetteer::find(place)
+            .map(|city| (city.lat, city.long))
+            .ok_or_else(|| {
+                format!(
+                    "unknown city {}, give a major city or coordinates as LAT,LONG",
+                    place
+                )
+            }),
+    }
+}
+
+#[derive(clap::Args, Debug, PartialEq)]
+pub struct FacetArgs {
+    /// Only the values containing this
+    filter: Option<String>,
+
+    /// Most values to list, the ones with the most stations first
+    #[arg(long)]
+    limit: Option<u32>,
+}
+
+#[derive(clap::Args, Debug, PartialEq)]
+pub struct AudioArgs {
+    /// EBU R128 loudness normalization
+    #[arg(long)]
+    loudnorm: Option<bool>,
+
+    /// Equalizer preset: flat, speech, music or bass-boost
+    #[arg(long)]
+    eq: Option<EqPreset>,
+
+    /// Limiter against clipping
+    #[arg(long)]
+    limiter: Option<bool>,
+}
+
+impl AudioArgs {
+    pub fn is_empty(&self) -> bool {
+        self.loudnorm.is_none() && self.eq.is_none() && self.limiter.is_none()
+    }
+
+    pub fn apply(&self, settings: &mut AudioSettings) {
+        if let Some(loudnorm) = self.loudnorm {
+            settings.loudnorm = loudnorm;
+        }
+        if let Some(eq) = self.eq {
+            settings.eq = eq;
+        }
+        if let Some(limiter) = self.limiter {
+            settings.limiter = limiter;
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> RadioResult<()> {
     let args = Args::parse();
 
-    let mut rclient = Rclient::new(&args.addr).await?;
+    let mut rclient = Rclient::new(&args.addr, args.zone.clone()).await?;
+    rclient.skip_silent = args.skip_silent;
     rclient.handle_args(args).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Commands {
+        let args = ["radio_client"].iter().chain(args);
+        Args::try_parse_from(args).unwrap().cmd
+    }
+
+    #[test]
+    fn audio_args_change_only_what_is_given() {
+        let Commands::Audio { settings } =
+            parse(&["audio", "--eq", "bass-boost", "--limiter", "true"])
+        else {
+            panic!("not the audio command");
+        };
+        assert!(!settings.is_empty());
+
+        let mut audio = AudioSettings {
+            loudnorm: true,
+            ..Default::default()
+        };
+        settings.apply(&mut audio);
+        assert_eq!(
+            audio,
+            AudioSettings {
+                loudnorm: true,
+                eq: EqPreset::BassBoost,
+                limiter: true,
+            }
+        );
+    }
+
+    #[test]
+    fn search_filters() {
+        let Commands::Search { name, filters } = parse(&[
+            "search",
+            "jazz",
+            "--tags",
+            "smooth,live",
+            "--countrycode",
+            "DE",
+            "--bitrate-min",
+            "128",
+            "--https",
+            "--hide-broken",
+            "--limit",
+            "20",
+        ]) else {
+            panic!("not the search command");
+        };
+        assert_eq!(name.as_deref(), Some("jazz"));
+
+        let mut data = SearchData::default();
+        filters.apply(&mut data);
+        assert_eq!(data.tag_list, ["smooth", "live"]);
+        assert_eq!(data.countrycode.as_deref(), Some("DE"));
+        assert_eq!(data.bitrate_min, Some(128));
+        assert_eq!(data.is_https, Some(true));
+        assert_eq!(data.has_geo_info, None);
+        assert!(data.hidebroken);
+        assert_eq!(data.limit, Some(20));
+    }
+
+    #[test]
+    fn search_order() {
+        let Commands::Search { filters, .. } = parse(&["search", "--order", "votes", "-r"]) else {
+            panic!("not the search command");
+        };
+        let mut data = SearchData::default();
+        filters.apply(&mut data);
+        assert_eq!(data.order, Some(StationOrder::Votes));
+        assert_eq!(data.reverse, Some(true));
+    }
+
+    #[test]
+    fn places_as_coordinates_or_cities() {
+        assert_eq!(parse_place("52.52, 13.40"), Ok((52.52, 13.40)));
+        assert_eq!(parse_place("-33.87,151.21"), Ok((-33.87, 151.21)));
+        assert_eq!(parse_place("berlin"), Ok((52.52, 13.40)));
+        assert_eq!(parse_place("Berlin, DE"), Ok((52.52, 13.40)));
+        assert!(parse_place("91,0").is_err());
+        assert!(parse_place("Atlantis").is_err());
+    }
+}
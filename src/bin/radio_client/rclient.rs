@@ -1,22 +1,30 @@
 use crate::server_initializer::{start_server, stop_server};
-use crate::{chooser, Commands};
+use crate::{chooser, history, AudioArgs, CacheAction, Commands, FacetArgs, FavsAction};
 use core::time;
 use radioklw::utils::{self, RadioError, RadioResult};
-use radioklw::{Client, RadioStation, SearchData, Server};
+use radioklw::{
+    AudioSettings, Client, Event, FacetKind, Near, PlayerStatus, ProbeReport, RadioStation,
+    SearchData, Server, TimeshiftAction,
+};
 use std::io::ErrorKind;
 use std::sync::Arc;
 use tokio::io::{self, AsyncWriteExt};
 use tokio::net;
+use tokio::sync::mpsc;
 
 const FAVS_FILE: &str = "favs.json";
+const SLOW_START_MS: u64 = 3000;
 
 #[derive(Debug, Clone)]
 pub struct Rclient {
     pub socket: std::sync::Arc<tokio::sync::Mutex<net::TcpStream>>,
+    pub zone: Option<String>,
+    /// Play the next station of the list when the current one goes silent
+    pub skip_silent: bool,
 }
 
 impl Rclient {
-    pub async fn new(addr: &str) -> Result<Self, RadioError> {
+    pub async fn new(addr: &str, zone: Option<String>) -> Result<Self, RadioError> {
         if net::TcpStream::connect(addr).await.is_err() {
             println!("Start the local streaming server...");
             start_server().await?;
@@ -27,53 +35,259 @@ impl Rclient {
             socket: std::sync::Arc::new(tokio::sync::Mutex::new(
                 net::TcpStream::connect(addr).await?,
             )),
+            zone,
+            skip_silent: false,
         })
     }
 
     pub async fn handle_args(&mut self, args: crate::Args) -> RadioResult<()> {
         match args.cmd {
-            Commands::Search { name } => {
-                let search_data = SearchData {
+            Commands::Search { name, filters } => {
+                let mut search_data = SearchData {
                     name: name.unwrap_or("".to_owned()),
                     country: args.country.unwrap_or("".to_owned()),
                     language: args.language.unwrap_or("".to_owned()),
+                    ..Default::default()
                 };
+                filters.apply(&mut search_data);
+                self.send_search_message(&search_data).await?;
+                self.recv_message().await?;
+            }
+            Commands::Near {
+                place: (lat, long),
+                radius,
+                filters,
+            } => {
+                let mut search_data = SearchData {
+                    country: args.country.unwrap_or("".to_owned()),
+                    language: args.language.unwrap_or("".to_owned()),
+                    near: Some(Near {
+                        lat,
+                        long,
+                        radius_km: radius,
+                    }),
+                    ..Default::default()
+                };
+                filters.apply(&mut search_data);
                 self.send_search_message(&search_data).await?;
                 self.recv_message().await?;
             }
             Commands::Stop => {
                 self.send_stop_message().await?;
             }
-            Commands::Favs => {
+            Commands::Favs { action } => {
                 let file_content = get_data_from_file_async().await?;
 
                 if !file_content.is_empty() {
                     let favorites: Vec<RadioStation> = serde_json::from_str(&file_content)?;
 
-                    let mut sviewer =
-                        chooser::StationViewer::new(favorites.clone().into(), true, self.clone());
-                    if let Some(curr_playing) = sviewer.run_chooser().await? {
-                        println!(
-                            "<<< Playing Radio station: {} >>>",
-                            favorites[curr_playing].name,
-                        );
+                    match action {
+                        Some(FavsAction::Check) => {
+                            self.check_favorites(&favorites).await?;
+                        }
+                        Some(FavsAction::Audio {
+                            number,
+                            settings,
+                            reset,
+                        }) => {
+                            set_favorite_audio(favorites, number, &settings, reset).await?;
+                        }
+                        None => {
+                            let mut sviewer = chooser::StationViewer::new(
+                                favorites.clone().into(),
+                                true,
+                                self.clone(),
+                            );
+                            if let Some(playing) = sviewer.run_chooser().await? {
+                                println!("<<< Playing Radio station: {} >>>", playing.name);
+                            }
+                        }
                     }
                 } else {
                     println!("Favorites list is empty");
                 }
             }
+            Commands::Vote => {
+                self.send_status_message().await?;
+                let Some(station) = self.recv_status().await?.station else {
+                    return Err("No station is playing".into());
+                };
+                println!("Voted for {}", self.vote(&station).await?.name);
+            }
+            Commands::Status => {
+                self.send_status_message().await?;
+                print_status(&self.recv_status().await?);
+            }
+            Commands::Volume { volume } => {
+                self.send_request(Client::SetVolume {
+                    zone: self.zone.clone(),
+                    volume,
+                })
+                .await?;
+                print_status(&self.recv_status().await?);
+            }
+            Commands::Device {
+                name,
+                server: false,
+            } => {
+                self.send_request(Client::SetDevice {
+                    zone: self.zone.clone(),
+                    device: name,
+                })
+                .await?;
+                print_status(&self.recv_status().await?);
+            }
+            Commands::Device { name, server: true } => {
+                self.send_request(Client::SetServerDevice { device: name })
+                    .await?;
+                self.print_devices().await?;
+            }
+            Commands::Devices => {
+                self.send_request(Client::ListDevices).await?;
+                self.print_devices().await?;
+            }
+            Commands::Pause => self.timeshift(TimeshiftAction::Pause).await?,
+            Commands::Resume => self.timeshift(TimeshiftAction::Resume).await?,
+            Commands::Rewind { secs } => self.timeshift(TimeshiftAction::Back(secs)).await?,
+            Commands::Forward { secs } => self.timeshift(TimeshiftAction::Forward(secs)).await?,
+            Commands::Live => self.timeshift(TimeshiftAction::Live).await?,
+            Commands::Events => {
+                self.send_request(Client::Subscribe).await?;
+                while let Some(reply) = self.recv_reply().await? {
+                    match reply {
+                        // Several a second, they are for the level meter
+                        Server::Event { event } if matches!(*event, Event::Level { .. }) => {}
+                        Server::Event { event } => {
+                            println!("{} {}", chrono::Local::now().format("%H:%M:%S"), event);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Commands::Countries { facets } => self.facets(FacetKind::Countries, facets).await?,
+            Commands::Languages { facets } => self.facets(FacetKind::Languages, facets).await?,
+            Commands::Tags { facets } => self.facets(FacetKind::Tags, facets).await?,
+            Commands::Codecs { facets } => self.facets(FacetKind::Codecs, facets).await?,
+            Commands::States { facets } => self.facets(FacetKind::States, facets).await?,
+            Commands::Cache { action } => {
+                let request = match action {
+                    CacheAction::Stats => Client::CacheStats,
+                    CacheAction::Clear => Client::ClearCache,
+                };
+                self.send_request(request).await?;
+                match self.recv_reply().await? {
+                    Some(Server::Cache { stats }) => {
+                        if action == CacheAction::Clear {
+                            println!("Cache cleared");
+                        }
+                        println!("{}", stats);
+                    }
+                    Some(Server::Error(message)) => return Err(message.into()),
+                    _ => return Err("Unexpected reply from the server".into()),
+                }
+            }
+            Commands::Zones => {
+                self.send_request(Client::ListZones).await?;
+                match self.recv_reply().await? {
+                    Some(Server::Zones { zones }) if !zones.is_empty() => {
+                        zones.iter().for_each(print_status);
+                    }
+                    Some(Server::Zones { .. }) => println!("No zones in use"),
+                    Some(Server::Error(message)) => return Err(message.into()),
+                    _ => return Err("Unexpected reply from the server".into()),
+                }
+            }
+            Commands::History { from, to, format } => {
+                self.send_request(Client::History {
+                    from: from.map(history::day_start).transpose()?,
+                    to: to.map(history::day_end).transpose()?,
+                })
+                .await?;
+
+                match self.recv_reply().await? {
+                    Some(Server::History { entries }) => history::print_history(&entries, format)?,
+                    Some(Server::Error(message)) => return Err(message.into()),
+                    _ => return Err("Unexpected reply from the server".into()),
+                }
+            }
+            Commands::Audio { settings } => {
+                self.send_request(Client::GetAudio).await?;
+                let mut current = self.recv_audio().await?;
+
+                if !settings.is_empty() {
+                    settings.apply(&mut current);
+                    self.send_request(Client::SetAudio {
+                        settings: Arc::new(current),
+                    })
+                    .await?;
+                    current = self.recv_audio().await?;
+                }
+                println!("Server audio: {}", current);
+            }
             Commands::StopServer => {
                 println!("Stop the local streaming server...");
-                self.send_stop_message().await?;
                 stop_server().await?;
             }
         }
         Ok(())
     }
 
-    pub async fn send_play_message(&mut self, station: &str) -> RadioResult<()> {
+    async fn print_devices(&self) -> RadioResult<()> {
+        let (devices, server_device) = match self.recv_reply().await? {
+            Some(Server::Devices {
+                devices,
+                server_device,
+            }) => (devices, server_device),
+            Some(Server::Error(message)) => return Err(message.into()),
+            _ => return Err("Unexpected reply from the server".into()),
+        };
+
+        if devices.is_empty() {
+            println!("No output devices found");
+        }
+        for device in devices.iter() {
+            let mut marks = Vec::new();
+            if device.default {
+                marks.push("system default");
+            }
+            if server_device.as_deref() == Some(device.name.as_str()) {
+                marks.push("server device");
+            }
+
+            print!("{}", device.name);
+            if let Some(description) = &device.description {
+                print!(" - {}", description);
+            }
+            if !marks.is_empty() {
+                print!(" ({})", marks.join(", "));
+            }
+            println!();
+        }
+        println!(
+            "Server output device: {}",
+            server_device.as_deref().unwrap_or("system default")
+        );
+        Ok(())
+    }
+
+    async fn timeshift(&mut self, action: TimeshiftAction) -> RadioResult<()> {
+        self.send_timeshift_message(action).await?;
+        print_status(&self.recv_status().await?);
+        Ok(())
+    }
+
+    pub async fn send_timeshift_message(&mut self, action: TimeshiftAction) -> RadioResult<()> {
+        self.send_request(Client::Timeshift {
+            zone: self.zone.clone(),
+            action,
+        })
+        .await
+    }
+
+    pub async fn send_play_message(&mut self, station: &RadioStation) -> RadioResult<()> {
         let req = Some(Client::Play {
-            url: Arc::new(station.to_owned()),
+            zone: self.zone.clone(),
+            station: Arc::new(station.clone()),
         });
 
         let mut socket = self.socket.lock().await;
@@ -95,8 +309,15 @@ impl Rclient {
         Ok(())
     }
 
-    pub async fn send_stop_message(&mut self) -> RadioResult<()> {
-        let req = Some(Client::Stop);
+    async fn send_probe_message(&mut self, urls: Vec<String>) -> RadioResult<()> {
+        self.send_request(Client::Probe {
+            urls: Arc::new(urls),
+        })
+        .await
+    }
+
+    async fn send_request(&mut self, req: Client) -> RadioResult<()> {
+        let req = Some(req);
 
         let mut socket = self.socket.lock().await;
         utils::send_json(&mut *socket, &req).await?;
@@ -105,40 +326,169 @@ impl Rclient {
         Ok(())
     }
 
-    async fn recv_message(&self) -> RadioResult<()> {
-        let msg = {
-            let mut socket = self.socket.lock().await;
-            let mut buf = io::BufReader::new(&mut *socket);
-            utils::receive_one(&mut buf).await?
+    async fn send_status_message(&mut self) -> RadioResult<()> {
+        self.send_request(Client::Status {
+            zone: self.zone.clone(),
+        })
+        .await
+    }
+
+    pub async fn send_stop_message(&mut self) -> RadioResult<()> {
+        self.send_request(Client::Stop {
+            zone: self.zone.clone(),
+        })
+        .await?;
+
+        self.recv_status().await?;
+        Ok(())
+    }
+
+    pub async fn vote(&mut self, station: &RadioStation) -> RadioResult<Arc<RadioStation>> {
+        self.send_request(Client::Vote {
+            station: Arc::new(station.clone()),
+        })
+        .await?;
+
+        match self.recv_reply().await? {
+            Some(Server::Voted { station }) => Ok(station),
+            Some(Server::Error(message)) => Err(message.into()),
+            _ => Err("Unexpected reply from the server".into()),
+        }
+    }
+
+    async fn facets(&mut self, kind: FacetKind, args: FacetArgs) -> RadioResult<()> {
+        self.send_request(Client::ListFacets {
+            kind,
+            filter: args.filter,
+            limit: args.limit,
+        })
+        .await?;
+
+        match self.recv_reply().await? {
+            Some(Server::Facets { facets, .. }) if !facets.is_empty() => {
+                facets.iter().for_each(|facet| println!("{}", facet));
+            }
+            Some(Server::Facets { .. }) => println!("No {} found", kind),
+            Some(Server::Error(message)) => return Err(message.into()),
+            _ => return Err("Unexpected reply from the server".into()),
+        }
+        Ok(())
+    }
+
+    /// Subscribes to the server's events on a connection of their own, so
+    /// that they do not mix with the replies to the requests.
+    pub async fn subscribe(&self) -> RadioResult<mpsc::UnboundedReceiver<Arc<Event>>> {
+        let addr = self.socket.lock().await.peer_addr()?;
+        let mut events = Rclient {
+            socket: Arc::new(tokio::sync::Mutex::new(
+                net::TcpStream::connect(addr).await?,
+            )),
+            zone: self.zone.clone(),
+            skip_silent: false,
         };
+        events.send_request(Client::Subscribe).await?;
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok(Some(reply)) = events.recv_reply().await {
+                if let Server::Event { event } = reply {
+                    if tx.send(event).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(rx)
+    }
+
+    async fn recv_reply(&self) -> RadioResult<Option<Server>> {
+        let mut socket = self.socket.lock().await;
+        let mut buf = io::BufReader::new(&mut *socket);
+        utils::receive_one(&mut buf).await
+    }
+
+    pub async fn recv_status(&self) -> RadioResult<PlayerStatus> {
+        match self.recv_reply().await? {
+            Some(Server::Status { status }) => Ok((*status).clone()),
+            Some(Server::PlayerError { error }) => Err(error.to_string().into()),
+            Some(Server::Error(message)) => Err(message.into()),
+            _ => Err("Unexpected reply from the server".into()),
+        }
+    }
+
+    async fn recv_audio(&self) -> RadioResult<AudioSettings> {
+        match self.recv_reply().await? {
+            Some(Server::Audio { settings }) => Ok((*settings).clone()),
+            Some(Server::Error(message)) => Err(message.into()),
+            _ => Err("Unexpected reply from the server".into()),
+        }
+    }
 
-        if let Some(msg) = msg {
+    async fn recv_message(&self) -> RadioResult<()> {
+        if let Some(msg) = self.recv_reply().await? {
             match msg {
                 Server::RadioChList { radio_list } => {
                     let mut sviewer =
                         chooser::StationViewer::new(radio_list.clone(), false, self.clone());
-                    if let Some(curr_playing) = sviewer.run_chooser().await? {
-                        println!(
-                            "<<< Playing Radio station: {} >>>",
-                            radio_list[curr_playing].name,
-                        );
+                    if let Some(playing) = sviewer.run_chooser().await? {
+                        println!("<<< Playing Radio station: {} >>>", playing.name);
                     }
                 }
                 Server::Error(message) => {
                     return Err(message.into());
                 }
+                _ => {
+                    return Err("Unexpected reply from the server".into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn check_favorites(&mut self, favorites: &[RadioStation]) -> RadioResult<()> {
+        println!("Checking {} favorites...", favorites.len());
+        self.send_probe_message(favorites.iter().map(|fav| fav.url.clone()).collect())
+            .await?;
+
+        let reports = match self.recv_reply().await? {
+            Some(Server::ProbeReports { reports }) => reports,
+            Some(Server::Error(message)) => return Err(message.into()),
+            _ => return Err("Unexpected reply from the server".into()),
+        };
+
+        let mut problems = 0;
+        for (idx, (station, report)) in favorites.iter().zip(reports.iter()).enumerate() {
+            let (health, details) = probe_health(station, report);
+            if health != "OK" {
+                problems += 1;
             }
+            println!("{}: {:<8} {} - {}", idx + 1, health, station.name, details);
         }
+        println!(
+            "{} of {} favorites are dead or degraded",
+            problems,
+            favorites.len()
+        );
 
         Ok(())
     }
 
-    pub async fn delete_station_from_favorites(&mut self, selected: usize) -> RadioResult<()> {
+    pub async fn delete_station_from_favorites(
+        &mut self,
+        station: &RadioStation,
+    ) -> RadioResult<()> {
         let file_content = get_data_from_file_async().await?;
 
         let mut favorites: Vec<RadioStation> = serde_json::from_str(&file_content)?;
 
-        favorites.remove(selected);
+        // The list shown may be sorted differently than the file.
+        if let Some(index) = favorites
+            .iter()
+            .position(|favorite| favorite.stationuuid == station.stationuuid)
+        {
+            favorites.remove(index);
+        }
 
         tokio::fs::write(FAVS_FILE, serde_json::to_string_pretty(&favorites)?).await?;
 
@@ -154,7 +504,10 @@ impl Rclient {
             favorites = serde_json::from_str(&file_content)?;
         }
 
-        favorites.push(station.clone());
+        favorites.push(RadioStation {
+            distance_km: None,
+            ..station.clone()
+        });
 
         tokio::fs::write(FAVS_FILE, serde_json::to_string_pretty(&favorites)?).await?;
 
@@ -162,6 +515,124 @@ impl Rclient {
     }
 }
 
+async fn set_favorite_audio(
+    mut favorites: Vec<RadioStation>,
+    number: usize,
+    settings: &AudioArgs,
+    reset: bool,
+) -> RadioResult<()> {
+    if number == 0 || number > favorites.len() {
+        return Err(format!("No favorite number {}", number).into());
+    }
+    let favorite = &mut favorites[number - 1];
+
+    if reset {
+        favorite.audio = None;
+    } else if !settings.is_empty() {
+        let mut audio = favorite.audio.clone().unwrap_or_default();
+        settings.apply(&mut audio);
+        favorite.audio = Some(audio);
+    }
+
+    match &favorite.audio {
+        Some(audio) => println!("{}: {}", favorite.name, audio),
+        None => println!("{}: server audio settings", favorite.name),
+    }
+
+    tokio::fs::write(FAVS_FILE, serde_json::to_string_pretty(&favorites)?).await?;
+
+    Ok(())
+}
+
+/// Position of the playback relative to the live stream, e.g. "paused -1:30".
+pub fn live_offset(status: &PlayerStatus) -> String {
+    let offset = match status.behind_live_secs {
+        0 => "live".to_string(),
+        secs => format!("-{}:{:02}", secs / 60, secs % 60),
+    };
+    if status.paused {
+        format!("paused {}", offset)
+    } else {
+        offset
+    }
+}
+
+fn print_status(status: &PlayerStatus) {
+    let device = status.device.as_deref().unwrap_or("default device");
+    match &status.station {
+        Some(station) => println!(
+            "[{}] Playing now: {} ({}) [{}], track: {}, volume {}, {}, relay: {}",
+            status.zone,
+            station.name,
+            status.variant.clone().unwrap_or_default(),
+            live_offset(status),
+            status.title.as_deref().unwrap_or("-"),
+            status.volume,
+            device,
+            status.relay_url.as_deref().unwrap_or("-"),
+        ),
+        None => println!(
+            "[{}] Playing now: -, volume {}, {}",
+            status.zone, status.volume, device
+        ),
+    }
+}
+
+fn probe_health(station: &RadioStation, report: &ProbeReport) -> (&'static str, String) {
+    if !report.reachable {
+        let error = report.error.clone().unwrap_or_default();
+        return ("DEAD", format!("unreachable: {}", error));
+    }
+
+    let status = report.status.unwrap_or_default();
+    if !(200..300).contains(&status) {
+        return ("DEAD", format!("HTTP status {}", status));
+    }
+
+    let ttfb = match report.time_to_first_byte_ms {
+        Some(ttfb) => ttfb,
+        None => {
+            let error = report.error.clone().unwrap_or_default();
+            return ("DEAD", format!("no audio data: {}", error));
+        }
+    };
+
+    let content_type = report.content_type.clone().unwrap_or_default();
+    let mut details = format!(
+        "{}, {} kbps, first byte in {} ms",
+        if content_type.is_empty() {
+            "-"
+        } else {
+            &content_type
+        },
+        report.icy_bitrate.unwrap_or(station.bitrate),
+        ttfb
+    );
+
+    let mut issues = Vec::new();
+    if !content_type.is_empty()
+        && !content_type.starts_with("audio/")
+        && !content_type.starts_with("application/ogg")
+    {
+        issues.push("not an audio stream".to_string());
+    }
+    if ttfb > SLOW_START_MS {
+        issues.push("slow start".to_string());
+    }
+    if let Some(bitrate) = report.icy_bitrate {
+        if station.bitrate > 0 && bitrate < station.bitrate {
+            issues.push(format!("bitrate below listed {} kbps", station.bitrate));
+        }
+    }
+
+    if issues.is_empty() {
+        ("OK", details)
+    } else {
+        details.push_str(&format!(" ({})", issues.join(", ")));
+        ("DEGRADED", details)
+    }
+}
+
 async fn get_data_from_file_async() -> RadioResult<String> {
     // Try to open the file, create if it doesn't exist
     match tokio::fs::File::open(FAVS_FILE).await {
@@ -182,3 +653,87 @@ async fn get_data_from_file_async() -> RadioResult<String> {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listed(bitrate: u32) -> RadioStation {
+        RadioStation {
+            bitrate,
+            ..Default::default()
+        }
+    }
+
+    fn answered(content_type: &str, icy_bitrate: Option<u32>, ttfb: u64) -> ProbeReport {
+        ProbeReport {
+            reachable: true,
+            status: Some(200),
+            content_type: Some(content_type.to_string()),
+            icy_bitrate,
+            time_to_first_byte_ms: Some(ttfb),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn healthy_streams() {
+        let (health, details) = probe_health(&listed(128), &answered("audio/mpeg", None, 200));
+        assert_eq!(health, "OK");
+        assert_eq!(details, "audio/mpeg, 128 kbps, first byte in 200 ms");
+
+        let (health, _) = probe_health(&listed(0), &answered("application/ogg", Some(96), 200));
+        assert_eq!(health, "OK");
+    }
+
+    #[test]
+    fn dead_streams() {
+        let unreachable = ProbeReport {
+            error: Some(String::from("connection refused")),
+            ..Default::default()
+        };
+        assert_eq!(
+            probe_health(&listed(128), &unreachable),
+            ("DEAD", String::from("unreachable: connection refused"))
+        );
+
+        let not_found = ProbeReport {
+            status: Some(404),
+            ..answered("text/html", None, 0)
+        };
+        assert_eq!(
+            probe_health(&listed(128), &not_found),
+            ("DEAD", String::from("HTTP status 404"))
+        );
+
+        let silent = ProbeReport {
+            time_to_first_byte_ms: None,
+            error: Some(String::from("stream ended without data")),
+            ..answered("audio/mpeg", None, 0)
+        };
+        assert_eq!(probe_health(&listed(128), &silent).0, "DEAD");
+    }
+
+    #[test]
+    fn degraded_streams_list_their_issues() {
+        let (health, details) = probe_health(&listed(128), &answered("text/html", Some(64), 5000));
+        assert_eq!(health, "DEGRADED");
+        assert_eq!(
+            details,
+            "text/html, 64 kbps, first byte in 5000 ms (not an audio stream, slow start, bitrate below listed 128 kbps)"
+        );
+    }
+
+    #[test]
+    fn offsets_from_live() {
+        let mut status = PlayerStatus::default();
+        assert_eq!(live_offset(&status), "live");
+
+        status.behind_live_secs = 90;
+        assert_eq!(live_offset(&status), "-1:30");
+
+        status.paused = true;
+        status.behind_live_secs = 5;
+        assert_eq!(live_offset(&status), "paused -0:05");
+    }
+}
@@ -0,0 +1,147 @@
+use radioklw::utils::RadioResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const CONFIG_FILE: &str = "server_config.json";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Settings {
+    /// Output device of the zones without their own
+    device: Option<String>,
+    #[serde(default)]
+    zone_devices: HashMap<String, String>,
+}
+
+/// Server settings changed by clients that are kept across restarts.
+#[derive(Debug, Clone)]
+pub struct Config {
+    file: PathBuf,
+    settings: Arc<Mutex<Settings>>,
+}
+
+impl Config {
+    pub async fn load() -> RadioResult<Self> {
+        Self::open(Path::new(CONFIG_FILE)).await
+    }
+
+    /// Reads the settings from the file. A file that cannot be read is kept
+    /// as a backup, so that saving the defaults does not overwrite it.
+    async fn open(file: &Path) -> RadioResult<Self> {
+        let settings = match tokio::fs::read_to_string(file).await {
+            Ok(content) if !content.trim().is_empty() => match serde_json::from_str(&content) {
+                Ok(settings) => settings,
+                Err(err) => {
+                    let mut backup = file.as_os_str().to_owned();
+                    backup.push(".bak");
+                    tokio::fs::rename(file, &backup).await?;
+                    eprintln!(
+                        "Unable to read the settings in {}, moved them to {}: {}",
+                        file.display(),
+                        Path::new(&backup).display(),
+                        err
+                    );
+                    Settings::default()
+                }
+            },
+            Ok(_) => Settings::default(),
+            Err(error) if error.kind() == ErrorKind::NotFound => Settings::default(),
+            Err(error) => {
+                let err = format!("failed to open the file {}: {:?}", file.display(), error);
+                return Err(err.into());
+            }
+        };
+
+        Ok(Config {
+            file: file.to_path_buf(),
+            settings: Arc::new(Mutex::new(settings)),
+        })
+    }
+
+    pub async fn device(&self) -> Option<String> {
+        self.settings.lock().await.device.clone()
+    }
+
+    pub async fn zone_device(&self, zone: &str) -> Option<String> {
+        self.settings.lock().await.zone_devices.get(zone).cloned()
+    }
+
+    pub async fn set_device(&self, device: Option<String>) {
+        let mut settings = self.settings.lock().await;
+        settings.device = device;
+        save(&self.file, &settings).await;
+    }
+
+    pub async fn set_zone_device(&self, zone: &str, device: Option<String>) {
+        let mut settings = self.settings.lock().await;
+        match device {
+            Some(device) => settings.zone_devices.insert(zone.to_string(), device),
+            None => settings.zone_devices.remove(zone),
+        };
+        save(&self.file, &settings).await;
+    }
+}
+
+async fn save(file: &Path, settings: &Settings) {
+    let result = match serde_json::to_string_pretty(settings) {
+        Ok(json) => tokio::fs::write(file, json)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("Unable to save the config to {}: {}", file.display(), err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_without_zone_devices() {
+        let settings: Settings = serde_json::from_str(r#"{"device": "hw:0"}"#).unwrap();
+        assert_eq!(settings.device.as_deref(), Some("hw:0"));
+        assert!(settings.zone_devices.is_empty());
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        let mut settings = Settings::default();
+        settings
+            .zone_devices
+            .insert(String::from("kitchen"), String::from("bluez_sink.speaker"));
+        let json = serde_json::to_string_pretty(&settings).unwrap();
+        let loaded: Settings = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(loaded.device, None);
+        assert_eq!(loaded.zone_devices, settings.zone_devices);
+    }
+
+    #[tokio::test]
+    async fn keeps_a_corrupt_file_as_backup() {
+        let file = std::env::temp_dir().join(format!("radio_config_{}.json", std::process::id()));
+        let backup = file.with_extension("json.bak");
+        std::fs::write(&file, r#"{"device": "hw:0""#).unwrap();
+
+        let config = Config::open(&file).await.unwrap();
+        assert_eq!(config.device().await, None);
+        assert!(!file.exists());
+        assert_eq!(
+            std::fs::read_to_string(&backup).unwrap(),
+            r#"{"device": "hw:0""#
+        );
+
+        config.set_device(Some(String::from("hw:1"))).await;
+        assert_eq!(
+            std::fs::read_to_string(&backup).unwrap(),
+            r#"{"device": "hw:0""#
+        );
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_file(&backup).unwrap();
+    }
+}
@@ -1,34 +1,127 @@
 use radioklw::utils::{self, RadioError, RadioResult};
-use radioklw::{Client, Server};
+use radioklw::{AudioSettings, Client, Server};
+use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncWriteExt, BufReader};
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
+use url::Url;
 
-use crate::player;
+use crate::config::Config;
+use crate::devices;
+use crate::events::Events;
+use crate::history::History;
+use crate::player::Backend;
+use crate::prober;
 use crate::radio_api::Seeker;
-use crate::radio_api::StationOrder;
-use player::*;
+use crate::ranking::Ranking;
+use crate::relay::Relay;
+use crate::scrobbler::Scrobbler;
+use crate::zone::{self, Zone, ZoneContext, DEFAULT_ZONE};
 
 const INDEX_URL: &str = "all.api.radio-browser.info";
 
 #[derive(Debug, Clone)]
 pub struct Connection {
-    pub is_playing: bool,
-    pub player: Player,
+    pub zones: Arc<Mutex<HashMap<String, Arc<Mutex<Zone>>>>>,
     pub seeker: Seeker,
+    pub context: ZoneContext,
+    pub config: Config,
+    pub audio: Arc<Mutex<AudioSettings>>,
 }
 
 impl Connection {
-    pub async fn new() -> Result<Self, RadioError> {
+    pub async fn new(
+        backend: Backend,
+        relay: Relay,
+        scrobbler: Option<Scrobbler>,
+        silence: Option<Duration>,
+        directories: &[Url],
+        station_db: bool,
+    ) -> Result<Self, RadioError> {
         Ok(Connection {
-            is_playing: false,
-            player: Player::new(),
-            seeker: Seeker::new(INDEX_URL).await?,
+            zones: Arc::new(Mutex::new(HashMap::new())),
+            seeker: Seeker::new(INDEX_URL, directories, station_db).await,
+            context: ZoneContext {
+                backend,
+                relay,
+                history: History::load().await?,
+                scrobbler,
+                events: Events::new(),
+                ranking: Ranking::default(),
+                silence,
+            },
+            config: Config::load().await?,
+            audio: Arc::new(Mutex::new(AudioSettings::default())),
         })
     }
 
-    pub async fn handle(&mut self, socket: TcpStream) -> RadioResult<()> {
+    /// Returns the addressed zone, creating it on first use.
+    async fn zone(&self, name: Option<String>) -> Arc<Mutex<Zone>> {
+        let name = name.unwrap_or_else(|| DEFAULT_ZONE.to_string());
+        let mut zones = self.zones.lock().await;
+        if let Some(zone) = zones.get(&name) {
+            return zone.clone();
+        }
+
+        let mut zone = Zone::new(&name, &self.context);
+        zone.player.device = self.config.zone_device(&name).await;
+        zone.player.server_device = self.config.device().await;
+
+        let zone = Arc::new(Mutex::new(zone));
+        zones.insert(name, zone.clone());
+        zone
+    }
+
+    /// Returns the addressed zone, which must have been played in before
+    /// unless it is the default one.
+    async fn existing_zone(&self, name: Option<String>) -> Result<Arc<Mutex<Zone>>, String> {
+        let name = name.unwrap_or_else(|| DEFAULT_ZONE.to_string());
+        if name == DEFAULT_ZONE {
+            return Ok(self.zone(Some(name)).await);
+        }
+
+        match self.zones.lock().await.get(&name) {
+            Some(zone) => Ok(zone.clone()),
+            None => Err(format!("Unknown zone {}", name)),
+        }
+    }
+
+    /// Stops the players of all zones before the server shuts down.
+    pub async fn stop_all(&self) {
+        let zones: Vec<_> = self.zones.lock().await.values().cloned().collect();
+        for zone in zones {
+            let mut zone = zone.lock().await;
+            if let Err(err) = zone.stop().await {
+                eprintln!("Unable to stop zone {}: {}", zone.name, err);
+            }
+        }
+    }
+
+    async fn devices(&self) -> Server {
+        Server::Devices {
+            devices: Arc::new(devices::list(&self.context.backend).await),
+            server_device: self.config.device().await,
+        }
+    }
+
+    /// Rejects device names the backend does not know, unless the devices
+    /// cannot be listed on this system.
+    async fn check_device(&self, device: Option<&str>) -> Result<(), String> {
+        let Some(device) = device else {
+            return Ok(());
+        };
+
+        let devices = devices::list(&self.context.backend).await;
+        if devices.is_empty() || devices.iter().any(|known| known.name == device) {
+            Ok(())
+        } else {
+            Err(format!("Unknown output device {}", device))
+        }
+    }
+
+    pub async fn handle(&self, socket: TcpStream) -> RadioResult<()> {
         let (read_half, write_half) = socket.into_split();
         let sender = Arc::new(Sender::new(write_half));
         let mut buffered = BufReader::new(read_half);
@@ -40,44 +133,262 @@ impl Connection {
             };
 
             let result = match req_res {
-                Client::Play { url } => {
-                    if self.is_playing {
-                        self.player.stop().await?;
-                        self.is_playing = false;
+                Client::Play { zone, station } => {
+                    let zone = self.zone(zone).await;
+
+                    let server_audio = self.audio.lock().await.clone();
+                    let played = zone::play(&zone, &station, &self.seeker, &server_audio).await;
+                    let reply = match played {
+                        Ok(()) => Server::Status {
+                            status: Arc::new(zone.lock().await.status()),
+                        },
+                        Err(error) => Server::PlayerError {
+                            error: Arc::new(error),
+                        },
+                    };
+                    sender.send(reply).await?;
+                    Ok(())
+                }
+                Client::Stop { zone } => match self.existing_zone(zone).await {
+                    Ok(zone) => {
+                        let mut zone = zone.lock().await;
+
+                        match zone.stop().await {
+                            Ok(()) => {
+                                sender
+                                    .send(Server::Status {
+                                        status: Arc::new(zone.status()),
+                                    })
+                                    .await?;
+                                Ok(())
+                            }
+                            Err(err) => Err(format!("Unable to stop the player: {}", err)),
+                        }
+                    }
+                    Err(message) => Err(message),
+                },
+                Client::Status { zone } => match self.existing_zone(zone).await {
+                    Ok(zone) => {
+                        let status = zone.lock().await.status();
+                        sender
+                            .send(Server::Status {
+                                status: Arc::new(status),
+                            })
+                            .await?;
+                        Ok(())
+                    }
+                    Err(message) => Err(message),
+                },
+                Client::SetVolume { zone, volume } => {
+                    if volume > 100 {
+                        Err("Volume must be between 0 and 100".to_string())
+                    } else {
+                        let zone = self.zone(zone).await;
+                        let mut zone = zone.lock().await;
+                        zone.player.volume = volume;
+
+                        match zone.restart().await {
+                            Ok(()) => {
+                                sender
+                                    .send(Server::Status {
+                                        status: Arc::new(zone.status()),
+                                    })
+                                    .await?;
+                                Ok(())
+                            }
+                            Err(message) => Err(message),
+                        }
+                    }
+                }
+                Client::SetDevice { zone, device } => {
+                    if let Err(message) = self.check_device(device.as_deref()).await {
+                        Err(message)
+                    } else {
+                        let zone = self.zone(zone).await;
+                        let mut zone = zone.lock().await;
+                        self.config
+                            .set_zone_device(&zone.name, device.clone())
+                            .await;
+                        zone.player.device = device;
+
+                        match zone.restart().await {
+                            Ok(()) => {
+                                sender
+                                    .send(Server::Status {
+                                        status: Arc::new(zone.status()),
+                                    })
+                                    .await?;
+                                Ok(())
+                            }
+                            Err(message) => Err(message),
+                        }
+                    }
+                }
+                Client::ListZones => {
+                    let zones: Vec<_> = self.zones.lock().await.values().cloned().collect();
+                    let mut statuses = Vec::with_capacity(zones.len());
+                    for zone in zones {
+                        statuses.push(zone.lock().await.status());
                     }
+                    statuses.sort_by(|a, b| a.zone.cmp(&b.zone));
+
+                    sender
+                        .send(Server::Zones {
+                            zones: Arc::new(statuses),
+                        })
+                        .await?;
+                    Ok(())
+                }
+                Client::ListDevices => {
+                    sender.send(self.devices().await).await?;
+                    Ok(())
+                }
+                Client::SetServerDevice { device } => {
+                    if let Err(message) = self.check_device(device.as_deref()).await {
+                        Err(message)
+                    } else {
+                        self.config.set_device(device.clone()).await;
 
-                    self.player.play(url).await?;
-                    self.is_playing = true;
+                        let zones: Vec<_> = self.zones.lock().await.values().cloned().collect();
+                        let mut result = Ok(());
+                        for zone in zones {
+                            let mut zone = zone.lock().await;
+                            if let Err(message) = zone.set_server_device(device.clone()).await {
+                                result = Err(message);
+                            }
+                        }
+
+                        if result.is_ok() {
+                            sender.send(self.devices().await).await?;
+                        }
+                        result
+                    }
+                }
+                Client::GetAudio => {
+                    let settings = self.audio.lock().await.clone();
+                    sender
+                        .send(Server::Audio {
+                            settings: Arc::new(settings),
+                        })
+                        .await?;
                     Ok(())
                 }
-                Client::Stop => {
-                    if self.is_playing {
-                        self.player.stop().await?;
-                        self.is_playing = false;
+                Client::SetAudio { settings } => {
+                    *self.audio.lock().await = (*settings).clone();
+
+                    let zones: Vec<_> = self.zones.lock().await.values().cloned().collect();
+                    let mut result = Ok(());
+                    for zone in zones {
+                        if let Err(message) = zone.lock().await.set_server_audio(&settings).await {
+                            result = Err(message);
+                        }
+                    }
+
+                    if result.is_ok() {
+                        sender.send(Server::Audio { settings }).await?;
                     }
+                    result
+                }
+                Client::History { from, to } => {
+                    let entries = self.context.history.query(from, to).await;
+                    sender
+                        .send(Server::History {
+                            entries: Arc::new(entries),
+                        })
+                        .await?;
+                    Ok(())
+                }
+                Client::Probe { urls } => {
+                    let reports = prober::probe_all(&urls).await;
+                    sender
+                        .send(Server::ProbeReports {
+                            reports: Arc::new(reports),
+                        })
+                        .await?;
+                    Ok(())
+                }
+                Client::Timeshift { zone, action } => match self.existing_zone(zone).await {
+                    Ok(zone) => {
+                        let mut zone = zone.lock().await;
 
+                        match zone.timeshift(action).await {
+                            Ok(()) => {
+                                sender
+                                    .send(Server::Status {
+                                        status: Arc::new(zone.status()),
+                                    })
+                                    .await?;
+                                Ok(())
+                            }
+                            Err(message) => Err(message),
+                        }
+                    }
+                    Err(message) => Err(message),
+                },
+                Client::Subscribe => {
+                    let mut events = self.context.events.subscribe();
+                    let sender = sender.clone();
+                    tokio::spawn(async move {
+                        while let Some(event) = events.next().await {
+                            if sender.send(Server::Event { event }).await.is_err() {
+                                break;
+                            }
+                        }
+                    });
+                    Ok(())
+                }
+                Client::ListFacets {
+                    kind,
+                    filter,
+                    limit,
+                } => match self.seeker.facets(kind, filter.as_deref(), limit).await {
+                    Ok(facets) => {
+                        sender
+                            .send(Server::Facets {
+                                kind,
+                                facets: Arc::new(facets),
+                            })
+                            .await?;
+                        Ok(())
+                    }
+                    Err(err) => Err(format!("Unable to list the {}: {}", kind, err)),
+                },
+                Client::Vote { station } => {
+                    match self.context.ranking.vote(&self.seeker, &station).await {
+                        Ok(()) => {
+                            sender.send(Server::Voted { station }).await?;
+                            Ok(())
+                        }
+                        Err(message) => Err(message),
+                    }
+                }
+                Client::CacheStats => {
+                    let stats = Arc::new(self.seeker.cache_stats().await);
+                    sender.send(Server::Cache { stats }).await?;
+                    Ok(())
+                }
+                Client::ClearCache => {
+                    let stats = Arc::new(self.seeker.clear_cache().await);
+                    sender.send(Server::Cache { stats }).await?;
                     Ok(())
                 }
                 Client::Search { search_data } => {
                     let stations = self
                         .seeker
                         .get_radio_station_search_mapper()
-                        .name(search_data.name.clone())
-                        .country(search_data.country.clone())
-                        .language(search_data.language.clone())
-                        .reverse(true)
-                        .order(StationOrder::Clickcount)
+                        .search_data(&search_data)
                         .send();
-                    let station_md = stations.await?;
 
-                    if !station_md.is_empty() {
-                        let rlist = Server::RadioChList {
-                            radio_list: Arc::new(station_md),
-                        };
-                        sender.send(rlist).await?;
-                        Ok(())
-                    } else {
-                        Err("No radio stations found.".to_string())
+                    match stations.await {
+                        Ok(station_md) if !station_md.is_empty() => {
+                            let rlist = Server::RadioChList {
+                                radio_list: Arc::new(station_md),
+                            };
+                            sender.send(rlist).await?;
+                            Ok(())
+                        }
+                        Ok(_) => Err("No radio stations found.".to_string()),
+                        Err(err) => Err(format!("Unable to search the stations: {}", err)),
                     }
                 }
             };
@@ -0,0 +1,154 @@
+use radioklw::OutputDevice;
+use tokio::process::Command;
+
+use crate::player::Backend;
+
+/// Lists the audio outputs the player backend can use.
+pub async fn list(backend: &Backend) -> Vec<OutputDevice> {
+    #[cfg(feature = "native-device")]
+    if let Backend::Native(crate::pipeline::SinkConfig::Device) = backend {
+        return tokio::task::spawn_blocking(native_devices)
+            .await
+            .unwrap_or_default();
+    }
+    let _ = backend;
+
+    // ffplay outputs through SDL, which uses PulseAudio (or PipeWire's
+    // PulseAudio server) when available and ALSA otherwise.
+    let sinks = pulse_sinks().await;
+    if !sinks.is_empty() {
+        return sinks;
+    }
+    alsa_devices().await
+}
+
+async fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().await.ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok()
+}
+
+async fn pulse_sinks() -> Vec<OutputDevice> {
+    let Some(sinks) = command_output("pactl", &["list", "short", "sinks"]).await else {
+        return Vec::new();
+    };
+    let default = command_output("pactl", &["get-default-sink"])
+        .await
+        .map(|name| name.trim().to_string());
+    parse_pulse_sinks(&sinks, default.as_deref())
+}
+
+/// `pactl list short sinks` prints one tab separated line per sink:
+/// index, name, driver, sample spec and state.
+fn parse_pulse_sinks(sinks: &str, default: Option<&str>) -> Vec<OutputDevice> {
+    sinks
+        .lines()
+        .filter_map(|line| line.split('\t').nth(1))
+        .map(|name| OutputDevice {
+            name: name.to_string(),
+            description: None,
+            default: default == Some(name),
+        })
+        .collect()
+}
+
+async fn alsa_devices() -> Vec<OutputDevice> {
+    let Some(pcms) = command_output("aplay", &["-L"]).await else {
+        return Vec::new();
+    };
+    parse_aplay(&pcms)
+}
+
+/// `aplay -L` prints the PCM names unindented, each followed by indented
+/// description lines.
+fn parse_aplay(pcms: &str) -> Vec<OutputDevice> {
+    let mut devices: Vec<OutputDevice> = Vec::new();
+    for line in pcms.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if !line.starts_with(char::is_whitespace) {
+            devices.push(OutputDevice {
+                name: line.to_string(),
+                description: None,
+                default: line == "default",
+            });
+        } else if let Some(device) = devices.last_mut() {
+            if device.description.is_none() {
+                device.description = Some(line.trim().to_string());
+            }
+        }
+    }
+    devices.retain(|device| device.name != "null");
+    devices
+}
+
+#[cfg(feature = "native-device")]
+fn native_devices() -> Vec<OutputDevice> {
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    let host = cpal::default_host();
+    let default = host
+        .default_output_device()
+        .and_then(|device| device.name().ok());
+    let Ok(devices) = host.output_devices() else {
+        return Vec::new();
+    };
+
+    devices
+        .filter_map(|device| device.name().ok())
+        .map(|name| OutputDevice {
+            default: default.as_deref() == Some(name.as_str()),
+            name,
+            description: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pulse_sinks_by_name() {
+        let sinks = "0\talsa_output.pci.analog-stereo\tmodule-alsa-card.c\ts16le 2ch 44100Hz\tSUSPENDED\n\
+                     1\tbluez_sink.headphones\tmodule-bluez5-device.c\ts16le 2ch 48000Hz\tRUNNING\n";
+        let devices = parse_pulse_sinks(sinks, Some("bluez_sink.headphones"));
+
+        let names: Vec<&str> = devices.iter().map(|device| device.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["alsa_output.pci.analog-stereo", "bluez_sink.headphones"]
+        );
+        assert!(!devices[0].default);
+        assert!(devices[1].default);
+    }
+
+    #[test]
+    fn aplay_names_with_their_first_description() {
+        let pcms = "null\n    Discard all samples (playback) or generate zero samples (capture)\n\
+                    default\n    Default ALSA Output (currently PipeWire Media Server)\n\
+                    hw:CARD=PCH,DEV=0\n    HDA Intel PCH, ALC257 Analog\n    Direct hardware device without any conversions\n";
+        let devices = parse_aplay(pcms);
+
+        assert_eq!(
+            devices,
+            [
+                OutputDevice {
+                    name: String::from("default"),
+                    description: Some(String::from(
+                        "Default ALSA Output (currently PipeWire Media Server)"
+                    )),
+                    default: true,
+                },
+                OutputDevice {
+                    name: String::from("hw:CARD=PCH,DEV=0"),
+                    description: Some(String::from("HDA Intel PCH, ALC257 Analog")),
+                    default: false,
+                },
+            ]
+        );
+    }
+}
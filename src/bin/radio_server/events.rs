@@ -0,0 +1,143 @@
+use radioklw::Event;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{Interval, MissedTickBehavior};
+
+// Subscribers that fall further behind miss events.
+const EVENT_QUEUE: usize = 64;
+
+// Levels are sent to the subscribers at this rate for the clients' meters.
+pub const LEVEL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Fans out server events to the subscribed clients. The frequent audio
+/// levels only keep the latest one of every zone, so that they never push
+/// the other events out of the queue.
+#[derive(Debug, Clone)]
+pub struct Events {
+    events: broadcast::Sender<Arc<Event>>,
+    levels: Arc<Mutex<HashMap<String, watch::Sender<f32>>>>,
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Events {
+            events: broadcast::channel(EVENT_QUEUE).0,
+            levels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Nobody listening is fine.
+        let _ = self.events.send(Arc::new(event));
+    }
+
+    /// Whether any client is subscribed, levels are only measured then.
+    pub fn subscribed(&self) -> bool {
+        self.events.receiver_count() > 0
+    }
+
+    pub fn set_level(&self, zone: &str, db: f32) {
+        let mut levels = self.levels.lock().unwrap_or_else(|err| err.into_inner());
+        match levels.get(zone) {
+            Some(level) => {
+                level.send_replace(db);
+            }
+            None => {
+                levels.insert(zone.to_string(), watch::channel(db).0);
+            }
+        }
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        let mut tick = tokio::time::interval(LEVEL_INTERVAL);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Subscription {
+            events: self.events.subscribe(),
+            all_levels: self.levels.clone(),
+            levels: HashMap::new(),
+            pending: VecDeque::new(),
+            tick,
+        }
+    }
+}
+
+/// The events of a subscribed client, with the changed levels of the zones
+/// every `LEVEL_INTERVAL`.
+#[derive(Debug)]
+pub struct Subscription {
+    events: broadcast::Receiver<Arc<Event>>,
+    all_levels: Arc<Mutex<HashMap<String, watch::Sender<f32>>>>,
+    levels: HashMap<String, watch::Receiver<f32>>,
+    pending: VecDeque<Event>,
+    tick: Interval,
+}
+
+impl Subscription {
+    /// The next event, `None` once the server stops publishing.
+    pub async fn next(&mut self) -> Option<Arc<Event>> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Arc::new(event));
+            }
+
+            tokio::select! {
+                event = self.events.recv() => match event {
+                    Ok(event) => return Some(event),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                },
+                _ = self.tick.tick() => self.changed_levels(),
+            }
+        }
+    }
+
+    fn changed_levels(&mut self) {
+        {
+            let all_levels = self
+                .all_levels
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            for (zone, level) in all_levels.iter() {
+                if !self.levels.contains_key(zone) {
+                    // The level at subscribing is news to the client too.
+                    let mut level = level.subscribe();
+                    level.mark_changed();
+                    self.levels.insert(zone.clone(), level);
+                }
+            }
+        }
+
+        for (zone, level) in self.levels.iter_mut() {
+            if level.has_changed().unwrap_or(false) {
+                let db = *level.borrow_and_update();
+                self.pending.push_back(Event::Level {
+                    zone: zone.clone(),
+                    db,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sends_the_current_level_of_a_zone() {
+        let events = Events::new();
+        events.set_level("kitchen", -20.0);
+        let mut subscription = events.subscribe();
+
+        let event = tokio::time::timeout(Duration::from_secs(1), subscription.next()).await;
+        assert_eq!(
+            event.unwrap().as_deref(),
+            Some(&Event::Level {
+                zone: String::from("kitchen"),
+                db: -20.0
+            })
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{DateTime, Utc};
+use radioklw::utils::RadioResult;
+use radioklw::{HistoryEntry, RadioStation, TrackEntry};
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// A change of the history, appended to the history file as a line of JSON
+/// so that a new track does not rewrite the whole file.
+#[derive(Debug, Serialize, Deserialize)]
+enum Record {
+    Start(HistoryEntry),
+    Track {
+        session: usize,
+        track: TrackEntry,
+    },
+    Stop {
+        session: usize,
+        stopped: DateTime<Utc>,
+    },
+}
+
+/// Persistent log of the played stations and the tracks seen while playing.
+#[derive(Debug, Clone)]
+pub struct History {
+    file: PathBuf,
+    entries: Arc<Mutex<Vec<HistoryEntry>>>,
+}
+
+impl History {
+    pub async fn load() -> RadioResult<Self> {
+        Self::open(Path::new(HISTORY_FILE)).await
+    }
+
+    /// Replays the records of the file, closes the sessions a previous
+    /// server left open and compacts the file. A file that cannot be read
+    /// is kept as a backup and the sessions read before the error are kept.
+    async fn open(file: &Path) -> RadioResult<Self> {
+        let content = match tokio::fs::read_to_string(file).await {
+            Ok(content) => content,
+            Err(error) if error.kind() == ErrorKind::NotFound => String::new(),
+            Err(error) => {
+                let err = format!("failed to open the file {}: {:?}", file.display(), error);
+                return Err(err.into());
+            }
+        };
+
+        let mut entries = Vec::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(record) => replay(&mut entries, record),
+                Err(err) => {
+                    let backup = backup_path(file);
+                    tokio::fs::rename(file, &backup).await?;
+                    eprintln!(
+                        "Unable to read the history in {}, moved it to {}: {}",
+                        file.display(),
+                        backup.display(),
+                        err
+                    );
+                    break;
+                }
+            }
+        }
+
+        // The last activity of a session is the closest to its end.
+        for entry in entries.iter_mut().filter(|entry| entry.stopped.is_none()) {
+            let last = entry
+                .tracks
+                .last()
+                .map_or(entry.started, |track| track.started);
+            entry.stopped = Some(last);
+        }
+        if let Err(err) = compact(file, &entries).await {
+            eprintln!("Unable to save the history to {}: {}", file.display(), err);
+        }
+
+        Ok(History {
+            file: file.to_path_buf(),
+            entries: Arc::new(Mutex::new(entries)),
+        })
+    }
+
+    /// Starts a listening session and returns its id.
+    pub async fn start(&self, zone: &str, station: &RadioStation) -> usize {
+        let mut entries = self.entries.lock().await;
+        let record = Record::Start(HistoryEntry {
+            zone: zone.to_string(),
+            station: station.name.clone(),
+            stationuuid: station.stationuuid.clone(),
+            started: Utc::now(),
+            stopped: None,
+            tracks: Vec::new(),
+        });
+        self.append(&mut entries, record).await;
+        entries.len() - 1
+    }
+
+    pub async fn add_track(&self, session: usize, title: String) {
+        let mut entries = self.entries.lock().await;
+        let track = TrackEntry {
+            title,
+            started: Utc::now(),
+        };
+        self.append(&mut entries, Record::Track { session, track })
+            .await;
+    }
+
+    pub async fn stop(&self, session: usize) {
+        let mut entries = self.entries.lock().await;
+        let stopped = Utc::now();
+        self.append(&mut entries, Record::Stop { session, stopped })
+            .await;
+    }
+
+    /// Applies the record and appends it to the file, while the entries are
+    /// locked so that the records are written in order.
+    async fn append(&self, entries: &mut Vec<HistoryEntry>, record: Record) {
+        let result = match serde_json::to_string(&record) {
+            Ok(json) => append_line(&self.file, &json)
+                .await
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = result {
+            eprintln!(
+                "Unable to save the history to {}: {}",
+                self.file.display(),
+                err
+            );
+        }
+
+        replay(entries, record);
+    }
+
+    /// Returns the sessions overlapping the given time range.
+    pub async fn query(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().await;
+        entries
+            .iter()
+            .filter(|entry| to.is_none_or(|to| entry.started <= to))
+            .filter(|entry| {
+                let stopped = entry.stopped.unwrap_or_else(Utc::now);
+                from.is_none_or(|from| stopped >= from)
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+fn replay(entries: &mut Vec<HistoryEntry>, record: Record) {
+    match record {
+        Record::Start(entry) => entries.push(entry),
+        Record::Track { session, track } => {
+            if let Some(entry) = entries.get_mut(session) {
+                entry.tracks.push(track);
+            }
+        }
+        Record::Stop { session, stopped } => {
+            if let Some(entry) = entries.get_mut(session) {
+                entry.stopped = Some(stopped);
+            }
+        }
+    }
+}
+
+fn backup_path(file: &Path) -> PathBuf {
+    let mut backup = file.as_os_str().to_owned();
+    backup.push(".bak");
+    backup.into()
+}
+
+async fn append_line(file: &Path, line: &str) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .await?;
+    file.write_all(format!("{}\n", line).as_bytes()).await?;
+    // Tokio writes in the background until flushed.
+    file.flush().await
+}
+
+/// Rewrites the file with a record per session, replacing it only once
+/// the new one is written.
+async fn compact(file: &Path, entries: &[HistoryEntry]) -> RadioResult<()> {
+    let mut content = String::new();
+    for entry in entries {
+        content.push_str(&serde_json::to_string(&Record::Start(entry.clone()))?);
+        content.push('\n');
+    }
+
+    let mut temporary = file.as_os_str().to_owned();
+    temporary.push(".tmp");
+    tokio::fs::write(&temporary, content).await?;
+    tokio::fs::rename(&temporary, file).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "radio_history_{}_{}.jsonl",
+            name,
+            std::process::id()
+        ))
+    }
+
+    fn station(name: &str) -> RadioStation {
+        RadioStation {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn appends_a_line_per_change() {
+        let file = temporary_file("append");
+        let history = History::open(&file).await.unwrap();
+        let session = history.start("default", &station("Jazz FM")).await;
+        history
+            .add_track(session, String::from("Miles Davis - So What"))
+            .await;
+        history.stop(session).await;
+
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(content.lines().count(), 3);
+
+        // Loading compacts the sessions into a line each.
+        let loaded = History::open(&file).await.unwrap();
+        assert_eq!(
+            loaded.query(None, None).await,
+            history.query(None, None).await
+        );
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn closes_dangling_sessions_at_their_last_track() {
+        let file = temporary_file("dangling");
+        let history = History::open(&file).await.unwrap();
+        let session = history.start("default", &station("Jazz FM")).await;
+        history
+            .add_track(session, String::from("Miles Davis - So What"))
+            .await;
+
+        let entries = History::open(&file).await.unwrap().query(None, None).await;
+        assert_eq!(entries[0].stopped, Some(entries[0].tracks[0].started));
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_a_corrupt_file_as_backup() {
+        let file = temporary_file("corrupt");
+        let history = History::open(&file).await.unwrap();
+        history.start("default", &station("Jazz FM")).await;
+        append_line(&file, "{\"Sta").await.unwrap();
+        let written = std::fs::read_to_string(&file).unwrap();
+
+        let entries = History::open(&file).await.unwrap().query(None, None).await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].station, "Jazz FM");
+        assert_eq!(
+            std::fs::read_to_string(backup_path(&file)).unwrap(),
+            written
+        );
+        assert_eq!(std::fs::read_to_string(&file).unwrap().lines().count(), 1);
+        std::fs::remove_file(&file).unwrap();
+        std::fs::remove_file(backup_path(&file)).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+// ICY (SHOUTcast/Icecast) in-band metadata handling.
+//
+// When a client sends `Icy-MetaData: 1`, the server answers with an
+// `icy-metaint` header and inserts a metadata block after every `metaint`
+// audio bytes. A block is one length byte (in units of 16 bytes) followed
+// by the padded metadata text, e.g. `StreamTitle='Artist - Title';`.
+
+const BLOCK_UNIT: usize = 16;
+
+#[derive(Debug)]
+enum State {
+    Audio(usize),
+    MetaLength,
+    Meta(usize),
+}
+
+/// Splits an ICY stream into audio bytes and metadata texts.
+#[derive(Debug)]
+pub struct IcyDemuxer {
+    metaint: Option<usize>,
+    state: State,
+    meta: Vec<u8>,
+}
+
+impl IcyDemuxer {
+    pub fn new(metaint: Option<usize>) -> Self {
+        IcyDemuxer {
+            metaint,
+            state: State::Audio(metaint.unwrap_or_default()),
+            meta: Vec::new(),
+        }
+    }
+
+    /// Appends the audio bytes of `chunk` to `audio` and returns the
+    /// metadata blocks completed within it.
+    pub fn push(&mut self, mut chunk: &[u8], audio: &mut Vec<u8>) -> Vec<String> {
+        let Some(metaint) = self.metaint else {
+            audio.extend_from_slice(chunk);
+            return Vec::new();
+        };
+
+        let mut blocks = Vec::new();
+        while !chunk.is_empty() {
+            match self.state {
+                State::Audio(remaining) => {
+                    let take = remaining.min(chunk.len());
+                    audio.extend_from_slice(&chunk[..take]);
+                    chunk = &chunk[take..];
+                    self.state = if take == remaining {
+                        State::MetaLength
+                    } else {
+                        State::Audio(remaining - take)
+                    };
+                }
+                State::MetaLength => {
+                    let length = chunk[0] as usize * BLOCK_UNIT;
+                    chunk = &chunk[1..];
+                    self.state = if length == 0 {
+                        State::Audio(metaint)
+                    } else {
+                        State::Meta(length)
+                    };
+                }
+                State::Meta(remaining) => {
+                    let take = remaining.min(chunk.len());
+                    self.meta.extend_from_slice(&chunk[..take]);
+                    chunk = &chunk[take..];
+                    if take == remaining {
+                        let text = String::from_utf8_lossy(&self.meta);
+                        blocks.push(text.trim_end_matches('\0').to_string());
+                        self.meta.clear();
+                        self.state = State::Audio(metaint);
+                    } else {
+                        self.state = State::Meta(remaining - take);
+                    }
+                }
+            }
+        }
+        blocks
+    }
+}
+
+/// Returns the `StreamTitle` of a metadata block, if it has one.
+pub fn stream_title(meta: &str) -> Option<String> {
+    let start = meta.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &meta[start..];
+    let end = rest.find("';").unwrap_or(rest.len());
+    Some(rest[..end].trim().to_string())
+}
+
+/// Encodes a title as a metadata block including its length byte.
+pub fn encode_title(title: &str) -> Vec<u8> {
+    let mut text = format!("StreamTitle='{}';", title.replace('\'', "’")).into_bytes();
+    text.truncate(255 * BLOCK_UNIT);
+
+    let blocks = text.len().div_ceil(BLOCK_UNIT);
+    text.resize(blocks * BLOCK_UNIT, 0);
+
+    let mut block = Vec::with_capacity(text.len() + 1);
+    block.push(blocks as u8);
+    block.extend_from_slice(&text);
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(metaint: usize, title: &str) -> Vec<u8> {
+        let mut stream = vec![1; metaint];
+        stream.extend(encode_title(title));
+        stream.extend(vec![2; metaint]);
+        stream.push(0);
+        stream.extend([3, 3]);
+        stream
+    }
+
+    #[test]
+    fn splits_audio_and_metadata() {
+        let mut demuxer = IcyDemuxer::new(Some(4));
+        let mut audio = Vec::new();
+        let blocks = demuxer.push(&stream(4, "Artist - Title"), &mut audio);
+
+        assert_eq!(audio, [1, 1, 1, 1, 2, 2, 2, 2, 3, 3]);
+        assert_eq!(blocks, ["StreamTitle='Artist - Title';"]);
+        assert_eq!(stream_title(&blocks[0]).as_deref(), Some("Artist - Title"));
+    }
+
+    #[test]
+    fn keeps_state_between_chunks() {
+        let mut demuxer = IcyDemuxer::new(Some(4));
+        let mut audio = Vec::new();
+        let mut blocks = Vec::new();
+        for byte in stream(4, "Artist - Title") {
+            blocks.extend(demuxer.push(&[byte], &mut audio));
+        }
+
+        assert_eq!(audio, [1, 1, 1, 1, 2, 2, 2, 2, 3, 3]);
+        assert_eq!(blocks, ["StreamTitle='Artist - Title';"]);
+    }
+
+    #[test]
+    fn passes_audio_through_without_metaint() {
+        let mut demuxer = IcyDemuxer::new(None);
+        let mut audio = Vec::new();
+        assert!(demuxer.push(&[5, 0, 7], &mut audio).is_empty());
+        assert_eq!(audio, [5, 0, 7]);
+    }
+
+    #[test]
+    fn titles_of_metadata_blocks() {
+        assert_eq!(
+            stream_title("StreamTitle='A - B';StreamUrl='';").as_deref(),
+            Some("A - B")
+        );
+        assert_eq!(stream_title("StreamTitle=' Open").as_deref(), Some("Open"));
+        assert_eq!(stream_title("StreamUrl='http://a.example/';"), None);
+    }
+
+    #[test]
+    fn encoded_titles_fill_whole_blocks() {
+        let block = encode_title("It's");
+        assert_eq!(block[0] as usize * BLOCK_UNIT, block.len() - 1);
+        let text = String::from_utf8_lossy(&block[1..]);
+        assert_eq!(
+            stream_title(text.trim_end_matches('\0')).as_deref(),
+            Some("It’s")
+        );
+    }
+}
@@ -2,10 +2,34 @@ use tokio::net;
 //use tokio::io::{AsyncBufReadExt, BufReader};
 use clap::Parser;
 use connection::Connection;
+#[cfg(feature = "native-audio")]
+use pipeline::SinkConfig;
+use player::Backend;
 use radioklw::utils::RadioResult;
+use relay::Relay;
+use scrobbler::Scrobbler;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use url::Url;
+mod config;
 mod connection;
+mod devices;
+mod events;
+mod history;
+mod icy;
+mod mock_directory;
+#[cfg(feature = "native-audio")]
+mod pipeline;
 mod player;
+mod player_error;
+mod prober;
 mod radio_api;
+mod ranking;
+mod relay;
+mod resolver;
+mod scrobbler;
+mod zone;
 
 extern crate lazy_static;
 
@@ -19,19 +43,112 @@ pub struct Args {
     /// Server address
     #[arg(short, long, default_value_t = String::from("localhost:8080"))]
     addr: String,
+
+    /// Address of the HTTP relay re-serving the playing stations
+    /// (e.g. 0.0.0.0:8000 to share them in the local network)
+    #[arg(short, long, default_value_t = String::from("127.0.0.1:0"))]
+    relay_addr: String,
+
+    /// Minutes of every relayed stream kept for pausing and rewinding,
+    /// 0 disables timeshift
+    #[arg(short, long, default_value_t = 10)]
+    timeshift_minutes: u64,
+
+    /// Base URL of a radio-browser compatible directory to use instead of
+    /// the radio-browser servers found through DNS, can be repeated
+    #[arg(long, conflicts_with = "mock_directory")]
+    directory: Vec<Url>,
+
+    /// Serve a fixed station list and test streams from a local stand-in of
+    /// the directory, for testing without the internet
+    #[arg(long)]
+    mock_directory: bool,
+
+    /// Station list of the mock directory instead of the built-in one, as
+    /// radio-browser station JSON where {directory} is replaced with the
+    /// mock's base URL
+    #[arg(long, requires = "mock_directory")]
+    mock_fixtures: Option<PathBuf>,
+
+    /// Download all stations of the directory to station_db.json, keep them
+    /// up to date and search them locally
+    #[arg(long)]
+    station_db: bool,
+
+    /// Seconds of silence after which a zone reports dead air,
+    /// 0 disables silence detection
+    #[arg(long, default_value_t = 30)]
+    silence_secs: u64,
+
+    /// Base URL of a ListenBrainz-compatible API to scrobble the played
+    /// tracks to (e.g. https://api.listenbrainz.org)
+    #[arg(long, requires = "scrobble_token")]
+    scrobble_url: Option<String>,
+
+    /// User token for the scrobbling API
+    #[arg(long, requires = "scrobble_url")]
+    scrobble_token: Option<String>,
+
+    /// Decode the streams in process instead of playing them with ffplay and
+    /// write the audio to: device, stdout, wav:<file> or raw:<file>
+    #[cfg(feature = "native-audio")]
+    #[arg(long)]
+    sink: Option<SinkConfig>,
 }
 
 #[tokio::main]
 async fn main() -> RadioResult<()> {
     let args = Args::parse();
 
-    let mut conn_handler = Connection::new().await?;
+    let timeshift = Duration::from_secs(args.timeshift_minutes * 60);
+    let (relay, relay_listener) = Relay::bind(&args.relay_addr, timeshift).await?;
+    tokio::spawn(relay.clone().serve(relay_listener));
+
+    let scrobbler = match (&args.scrobble_url, &args.scrobble_token) {
+        (Some(url), Some(token)) => Some(Scrobbler::new(url, token).await?),
+        _ => None,
+    };
+
+    #[cfg(feature = "native-audio")]
+    let backend = args.sink.clone().map(Backend::Native).unwrap_or_default();
+    #[cfg(not(feature = "native-audio"))]
+    let backend = Backend::default();
+
+    let silence = (args.silence_secs > 0).then(|| Duration::from_secs(args.silence_secs));
+    let directories = if args.mock_directory {
+        vec![mock_directory::start(args.mock_fixtures.as_deref()).await?]
+    } else {
+        args.directory.clone()
+    };
+    let conn_handler = Connection::new(
+        backend,
+        relay,
+        scrobbler,
+        silence,
+        &directories,
+        args.station_db,
+    )
+    .await?;
     let listener = net::TcpListener::bind(args.addr).await?;
+    let mut terminate = signal(SignalKind::terminate())?;
 
     loop {
-        let (socket, _) = listener.accept().await?;
-        log_error(conn_handler.handle(socket).await);
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, _) = accepted?;
+                let conn_handler = conn_handler.clone();
+                tokio::spawn(async move {
+                    log_error(conn_handler.handle(socket).await);
+                });
+            }
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
     }
+
+    // Players would outlive the server otherwise.
+    conn_handler.stop_all().await;
+    Ok(())
 }
 
 fn log_error(result: RadioResult<()>) {
@@ -0,0 +1,311 @@
+//! A stand-in for the radio-browser API that serves a fixed station list and
+//! generated test streams, so that searching and playing work without the
+//! internet.
+
+use crate::radio_api::filters;
+use radioklw::utils::RadioResult;
+use radioklw::RadioStation;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::f32::consts::TAU;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use url::Url;
+
+const FIXTURES: &str = include_str!("../../../fixtures/stations.json");
+// Replaced with the base URL of the mock in the fixtures.
+const BASE_PLACEHOLDER: &str = "{directory}";
+
+const SAMPLE_RATE: u32 = 22050;
+const TONE_HZ: f32 = 440.0;
+const CHUNK: Duration = Duration::from_millis(100);
+
+/// Starts the mock directory on a free local port and returns its URL.
+pub async fn start(fixtures: Option<&Path>) -> RadioResult<Url> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let base = format!("http://{}", listener.local_addr()?);
+
+    let fixtures = match fixtures {
+        Some(path) => tokio::fs::read_to_string(path).await?,
+        None => FIXTURES.to_string(),
+    };
+    let stations: Vec<RadioStation> =
+        serde_json::from_str(&fixtures.replace(BASE_PLACEHOLDER, &base))?;
+    eprintln!(
+        "Mock directory with {} stations at {}",
+        stations.len(),
+        base
+    );
+
+    tokio::spawn(serve(listener, Arc::new(stations)));
+    Ok(Url::parse(&base)?)
+}
+
+async fn serve(listener: TcpListener, stations: Arc<Vec<RadioStation>>) {
+    loop {
+        let socket = match listener.accept().await {
+            Ok((socket, _)) => socket,
+            Err(err) => {
+                eprintln!("Mock directory accept failed: {}", err);
+                continue;
+            }
+        };
+
+        let stations = stations.clone();
+        tokio::spawn(async move {
+            if let Err(err) = respond(socket, &stations).await {
+                eprintln!("Mock directory error: {}", err);
+            }
+        });
+    }
+}
+
+async fn respond(mut socket: TcpStream, stations: &[RadioStation]) -> RadioResult<()> {
+    let (path, params) = read_request(&mut socket).await?;
+    let segments: Vec<String> = path
+        .trim_matches('/')
+        .split('/')
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let reply = match segments.as_slice() {
+        ["json", "stats"] => json!({
+            "supported_version": 1,
+            "software_version": "mock",
+            "status": "OK",
+            "stations": stations.len(),
+            "stations_broken": stations.iter().filter(|station| station.lastcheckok == 0).count(),
+            "tags": 0,
+            "clicks_last_hour": 0,
+            "clicks_last_day": 0,
+            "languages": 0,
+            "countries": 0,
+        }),
+        ["json", "stations"] | ["json", "stations", "search"] => {
+            json!(filters::search(stations.iter(), &params))
+        }
+        ["json", action @ ("url" | "vote"), uuid] => {
+            match stations.iter().find(|station| station.stationuuid == *uuid) {
+                Some(station) => json!({
+                    "ok": true,
+                    "message": if *action == "url" { "retrieved station url" } else { "voted for station successfully" },
+                    "stationuuid": station.stationuuid,
+                    "name": station.name,
+                    "url": station.url,
+                }),
+                None => json!({ "ok": false, "message": "station not found" }),
+            }
+        }
+        ["json", kind, filter @ ..] if filter.len() <= 1 => {
+            match facets(stations, kind, filter.first().copied(), &params) {
+                Some(facets) => facets,
+                None => return not_found(&mut socket).await,
+            }
+        }
+        ["mock", "tone.wav"] => return stream(&mut socket, TONE_HZ).await,
+        ["mock", "silence.wav"] => return stream(&mut socket, 0.0).await,
+        _ => return not_found(&mut socket).await,
+    };
+
+    let body = reply.to_string();
+    let head = format!(
+        "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    Ok(())
+}
+
+async fn not_found(socket: &mut TcpStream) -> RadioResult<()> {
+    socket
+        .write_all(b"HTTP/1.0 404 Not Found\r\nConnection: close\r\n\r\n")
+        .await?;
+    Ok(())
+}
+
+/// Reads the request path and the parameters of the JSON body, which the
+/// directory clients send as an object of strings.
+async fn read_request(socket: &mut TcpStream) -> RadioResult<(String, HashMap<String, String>)> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    let head_end = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end + 4;
+        }
+        let read = socket.read(&mut buf).await?;
+        if read == 0 {
+            break request.len();
+        }
+        request.extend_from_slice(&buf[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&request[..head_end]).to_string();
+    let length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while request.len() < head_end + length {
+        let read = socket.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let path = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .unwrap_or("/");
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+    let body = &request[head_end.min(request.len())..];
+    let params = serde_json::from_slice::<HashMap<String, Value>>(body)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(name, value)| match value {
+            Value::String(value) => (name, value),
+            value => (name, value.to_string()),
+        })
+        .collect();
+
+    Ok((path.to_string(), params))
+}
+
+fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = segment
+            .get(i + 1..i + 3)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Counts the stations per country, language, tag, codec or state.
+fn facets(
+    stations: &[RadioStation],
+    kind: &str,
+    filter: Option<&str>,
+    params: &HashMap<String, String>,
+) -> Option<Value> {
+    // The value, its extra field and the number of stations with it
+    let mut counts: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut count = |name: &str, extra: &str| {
+        if filter.is_none_or(|filter| filters::matches(name, filter, false)) {
+            *counts
+                .entry((name.to_string(), extra.to_string()))
+                .or_default() += 1;
+        }
+    };
+
+    let extra_field = match kind {
+        "countries" => {
+            stations
+                .iter()
+                .for_each(|station| count(&station.country, &station.countrycode));
+            "iso_3166_1"
+        }
+        "languages" => {
+            for station in stations {
+                filters::list(&station.language).for_each(|language| count(language, ""));
+            }
+            "iso_639"
+        }
+        "tags" => {
+            for station in stations {
+                filters::list(&station.tags).for_each(|tag| count(tag, ""));
+            }
+            ""
+        }
+        "codecs" => {
+            stations
+                .iter()
+                .for_each(|station| count(&station.codec, ""));
+            ""
+        }
+        "states" => {
+            stations
+                .iter()
+                .for_each(|station| count(&station.state, &station.country));
+            "country"
+        }
+        _ => return None,
+    };
+
+    let mut facets: Vec<_> = counts.into_iter().collect();
+    facets.sort_by_key(|facet| std::cmp::Reverse(facet.1));
+    let limit = params
+        .get("limit")
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(usize::MAX);
+
+    Some(Value::Array(
+        facets
+            .into_iter()
+            .take(limit)
+            .map(|((name, extra), stationcount)| {
+                let mut facet = json!({ "name": name, "stationcount": stationcount });
+                if !extra_field.is_empty() {
+                    facet[extra_field] = json!(extra);
+                }
+                facet
+            })
+            .collect(),
+    ))
+}
+
+/// Streams an endless 16-bit mono WAV of a sine tone, silence for 0 Hz, in
+/// real time.
+async fn stream(socket: &mut TcpStream, frequency: f32) -> RadioResult<()> {
+    let mut head = Vec::from(
+        &b"HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"[..],
+    );
+    // Unknown length, players read the data until the connection ends.
+    head.extend_from_slice(b"RIFF");
+    head.extend_from_slice(&u32::MAX.to_le_bytes());
+    head.extend_from_slice(b"WAVEfmt ");
+    head.extend_from_slice(&16u32.to_le_bytes());
+    head.extend_from_slice(&1u16.to_le_bytes());
+    head.extend_from_slice(&1u16.to_le_bytes());
+    head.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    head.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    head.extend_from_slice(&2u16.to_le_bytes());
+    head.extend_from_slice(&16u16.to_le_bytes());
+    head.extend_from_slice(b"data");
+    head.extend_from_slice(&(u32::MAX - 36).to_le_bytes());
+    socket.write_all(&head).await?;
+
+    let samples_per_chunk = (SAMPLE_RATE as u64 * CHUNK.as_millis() as u64 / 1000) as usize;
+    let mut phase = 0.0f32;
+    let mut interval = tokio::time::interval(CHUNK);
+    loop {
+        interval.tick().await;
+        let mut chunk = Vec::with_capacity(samples_per_chunk * 2);
+        for _ in 0..samples_per_chunk {
+            let sample = (phase.sin() * 0.3 * i16::MAX as f32) as i16;
+            chunk.extend_from_slice(&sample.to_le_bytes());
+            phase = (phase + TAU * frequency / SAMPLE_RATE as f32) % TAU;
+        }
+        socket.write_all(&chunk).await?;
+    }
+}
@@ -0,0 +1,340 @@
+//! In-process playback without FFmpeg: the stream is fetched over HTTP,
+//! demuxed and decoded with symphonia, resampled with rubato to the rate
+//! the sink needs and written to the sink.
+
+use bytes::Bytes;
+use rubato::{FftFixedIn, Resampler};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::{MediaSourceStream, ReadOnlySource};
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+use crate::player::AudioMonitor;
+use crate::radio_api::HTTP_CLIENT;
+
+mod sink;
+
+pub use sink::SinkConfig;
+
+// Give up on streams that do not produce audio within this time.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+const RESAMPLER_CHUNK: usize = 1024;
+const FETCH_QUEUE: usize = 64;
+
+/// Where and how loud the decoded audio is played.
+#[derive(Debug, Clone)]
+pub struct Output {
+    pub sink: SinkConfig,
+    pub device: Option<String>,
+    pub volume: u8,
+}
+
+/// A running pipeline, stopped when dropped.
+#[derive(Debug)]
+pub struct Playback {
+    stop: Arc<AtomicBool>,
+    fetcher: JoinHandle<()>,
+}
+
+impl Playback {
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        self.fetcher.abort();
+    }
+}
+
+impl Drop for Playback {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Starts playing the stream and returns once the first audio reached the
+/// sink, along with a receiver for the error that ends the playback. The
+/// decoded audio is passed to the monitor to detect silence.
+pub async fn start(
+    url: &str,
+    output: Output,
+    mut monitor: AudioMonitor,
+) -> io::Result<(Playback, oneshot::Receiver<io::Error>)> {
+    let mut response = HTTP_CLIENT
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(io::Error::other)?;
+
+    let mut hint = Hint::new();
+    if let Some(content_type) = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        hint.mime_type(content_type);
+    }
+
+    let (chunks_tx, chunks_rx) = mpsc::channel(FETCH_QUEUE);
+    let fetcher = tokio::spawn(async move {
+        while let Ok(Some(chunk)) = response.chunk().await {
+            if chunks_tx.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let playback = Playback {
+        stop: Arc::new(AtomicBool::new(false)),
+        fetcher,
+    };
+
+    let (ready_tx, ready_rx) = oneshot::channel();
+    let (failure_tx, failure_rx) = oneshot::channel();
+    let stop = playback.stop.clone();
+    let source = StreamReader {
+        chunks: chunks_rx,
+        current: Bytes::new(),
+    };
+    tokio::task::spawn_blocking(move || {
+        let mut ready = Some(ready_tx);
+        let result = run(source, hint, &output, &mut monitor, &stop, &mut ready);
+        match (result, ready.take()) {
+            (Err(err), Some(ready)) => {
+                let _ = ready.send(Err(err));
+            }
+            (Err(err), None) => {
+                let _ = failure_tx.send(err);
+            }
+            (Ok(()), _) if !stop.load(Ordering::Relaxed) => {
+                let err = io::Error::new(io::ErrorKind::UnexpectedEof, "the stream ended");
+                let _ = failure_tx.send(err);
+            }
+            (Ok(()), _) => {}
+        }
+    });
+
+    match tokio::time::timeout(START_TIMEOUT, ready_rx).await {
+        Ok(Ok(Ok(()))) => Ok((playback, failure_rx)),
+        Ok(Ok(Err(err))) => Err(err),
+        Ok(Err(_)) => Err(io::Error::other("the stream ended before playing")),
+        Err(_) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "no audio received from the stream",
+        )),
+    }
+}
+
+fn run(
+    source: StreamReader,
+    hint: Hint,
+    output: &Output,
+    monitor: &mut AudioMonitor,
+    stop: &AtomicBool,
+    ready: &mut Option<oneshot::Sender<io::Result<()>>>,
+) -> io::Result<()> {
+    let stream = MediaSourceStream::new(Box::new(ReadOnlySource::new(source)), Default::default());
+    let probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(decode_error)?;
+    let mut format = probed.format;
+
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or_else(|| io::Error::other("no audio track in the stream"))?;
+    let track_id = track.id;
+    let mut decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(decode_error)?;
+
+    let mut sink = output.sink.open(output.device.as_deref())?;
+    let gain = output.volume as f32 / 100.0;
+    let mut converter: Option<Converter> = None;
+
+    while !stop.load(Ordering::Relaxed) {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(Error::IoError(err)) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(decode_error(err)),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupt frames happen on radio streams, skip them.
+            Err(Error::DecodeError(_)) => continue,
+            Err(err) => return Err(decode_error(err)),
+        };
+        let spec = *decoded.spec();
+        let mut samples = SampleBuffer::<f32>::new(decoded.capacity() as u64, spec);
+        samples.copy_interleaved_ref(decoded);
+        monitor.samples(samples.samples(), spec.rate, spec.channels.count() as u16);
+
+        let converter = match &mut converter {
+            Some(converter) => converter,
+            None => {
+                let from = (spec.rate, spec.channels.count() as u16);
+                let to = sink.format().unwrap_or(from);
+                sink.start(to.0, to.1)?;
+                converter.insert(Converter::new(from, to)?)
+            }
+        };
+
+        let mut output = converter.process(samples.samples())?;
+        output.iter_mut().for_each(|sample| *sample *= gain);
+        sink.write(&output)?;
+
+        if let Some(ready) = ready.take() {
+            let _ = ready.send(Ok(()));
+        }
+    }
+
+    sink.finish()
+}
+
+fn decode_error(err: Error) -> io::Error {
+    match err {
+        Error::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+    }
+}
+
+/// Blocking reader over the chunks received by the fetch task.
+struct StreamReader {
+    chunks: mpsc::Receiver<Bytes>,
+    current: Bytes,
+}
+
+impl Read for StreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => self.current = chunk,
+                None => return Ok(0),
+            }
+        }
+
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Converts interleaved samples to the sample rate and channel count of
+/// the sink.
+struct Converter {
+    channels: (usize, usize),
+    resampler: Option<FftFixedIn<f32>>,
+    pending: Vec<Vec<f32>>,
+}
+
+impl Converter {
+    fn new(from: (u32, u16), to: (u32, u16)) -> io::Result<Self> {
+        let channels = (from.1 as usize, to.1 as usize);
+        let resampler = if from.0 == to.0 {
+            None
+        } else {
+            let resampler = FftFixedIn::new(
+                from.0 as usize,
+                to.0 as usize,
+                RESAMPLER_CHUNK,
+                1,
+                channels.1,
+            )
+            .map_err(io::Error::other)?;
+            Some(resampler)
+        };
+
+        Ok(Converter {
+            channels,
+            resampler,
+            pending: vec![Vec::new(); channels.1],
+        })
+    }
+
+    fn process(&mut self, samples: &[f32]) -> io::Result<Vec<f32>> {
+        let (from, to) = self.channels;
+        let frames = samples.len() / from.max(1);
+
+        // Missing channels repeat the existing ones, extra ones are dropped.
+        let remapped: Vec<f32> = (0..frames)
+            .flat_map(|frame| (0..to).map(move |channel| samples[frame * from + channel % from]))
+            .collect();
+
+        let Some(resampler) = &mut self.resampler else {
+            return Ok(remapped);
+        };
+
+        for (index, sample) in remapped.iter().enumerate() {
+            self.pending[index % to].push(*sample);
+        }
+
+        let mut output = Vec::new();
+        while self.pending[0].len() >= resampler.input_frames_next() {
+            let needed = resampler.input_frames_next();
+            let input: Vec<Vec<f32>> = self
+                .pending
+                .iter_mut()
+                .map(|channel| channel.drain(..needed).collect())
+                .collect();
+            let resampled = resampler.process(&input, None).map_err(io::Error::other)?;
+            for frame in 0..resampled[0].len() {
+                output.extend(resampled.iter().map(|channel| channel[frame]));
+            }
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_missing_channels() {
+        let mut converter = Converter::new((44100, 1), (44100, 2)).unwrap();
+        assert_eq!(
+            converter.process(&[0.1, 0.2]).unwrap(),
+            [0.1, 0.1, 0.2, 0.2]
+        );
+    }
+
+    #[test]
+    fn drops_extra_channels() {
+        let mut converter = Converter::new((44100, 3), (44100, 2)).unwrap();
+        assert_eq!(
+            converter.process(&[0.1, 0.2, 0.3, 0.4, 0.5, 0.6]).unwrap(),
+            [0.1, 0.2, 0.4, 0.5]
+        );
+    }
+
+    #[test]
+    fn resamples_whole_chunks() {
+        let mut converter = Converter::new((48000, 2), (24000, 2)).unwrap();
+
+        // Less than a chunk is kept until more samples come in.
+        let frame = [0.25, -0.25];
+        let samples: Vec<f32> = frame.repeat(RESAMPLER_CHUNK / 2);
+        assert!(converter.process(&samples).unwrap().is_empty());
+
+        let samples: Vec<f32> = frame.repeat(RESAMPLER_CHUNK * 3 + RESAMPLER_CHUNK / 2);
+        let output = converter.process(&samples).unwrap();
+        assert_eq!(output.len(), 4 * RESAMPLER_CHUNK / 2 * 2);
+    }
+}
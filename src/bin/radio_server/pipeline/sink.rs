@@ -0,0 +1,295 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Where the native pipeline writes the decoded audio.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SinkConfig {
+    /// The zone's output device, or the system default
+    Device,
+    /// 16 bit PCM WAV file
+    Wav(PathBuf),
+    /// Headerless signed 16 bit little endian PCM file
+    Raw(PathBuf),
+    /// Headerless signed 16 bit little endian PCM on stdout
+    Stdout,
+}
+
+impl FromStr for SinkConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("wav", path)) if !path.is_empty() => Ok(SinkConfig::Wav(path.into())),
+            Some(("raw", path)) if !path.is_empty() => Ok(SinkConfig::Raw(path.into())),
+            None if s == "stdout" => Ok(SinkConfig::Stdout),
+            None if s == "device" => {
+                if cfg!(feature = "native-device") {
+                    Ok(SinkConfig::Device)
+                } else {
+                    Err("the device sink needs the native-device feature".to_string())
+                }
+            }
+            _ => Err(format!(
+                "unknown sink {}, expected device, stdout, wav:<file> or raw:<file>",
+                s
+            )),
+        }
+    }
+}
+
+impl SinkConfig {
+    pub fn open(&self, device: Option<&str>) -> io::Result<Box<dyn Sink>> {
+        match self {
+            #[cfg(feature = "native-device")]
+            SinkConfig::Device => Ok(Box::new(device::DeviceSink::open(device)?)),
+            #[cfg(not(feature = "native-device"))]
+            SinkConfig::Device => {
+                let _ = device;
+                Err(io::Error::other("built without the native-device feature"))
+            }
+            SinkConfig::Wav(path) => Ok(Box::new(WavSink {
+                path: path.clone(),
+                writer: None,
+            })),
+            SinkConfig::Raw(path) => Ok(Box::new(RawSink(BufWriter::new(File::create(path)?)))),
+            SinkConfig::Stdout => Ok(Box::new(RawSink(io::stdout()))),
+        }
+    }
+}
+
+/// Consumer of interleaved `f32` samples.
+pub trait Sink {
+    /// Sample rate and channel count the sink needs, `None` to take the
+    /// format of the stream.
+    fn format(&self) -> Option<(u32, u16)>;
+
+    /// Called once with the format of the samples before the first write.
+    fn start(&mut self, sample_rate: u32, channels: u16) -> io::Result<()>;
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+struct WavSink {
+    path: PathBuf,
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+}
+
+impl Sink for WavSink {
+    fn format(&self) -> Option<(u32, u16)> {
+        None
+    }
+
+    fn start(&mut self, sample_rate: u32, channels: u16) -> io::Result<()> {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::create(&self.path, spec).map_err(io::Error::other)?;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let Some(writer) = self.writer.as_mut() else {
+            return Err(io::Error::other("WAV sink not started"));
+        };
+        for sample in samples {
+            writer
+                .write_sample(to_i16(*sample))
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize().map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+}
+
+struct RawSink<W: Write>(W);
+
+impl<W: Write> Sink for RawSink<W> {
+    fn format(&self) -> Option<(u32, u16)> {
+        None
+    }
+
+    fn start(&mut self, _sample_rate: u32, _channels: u16) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|sample| to_i16(*sample).to_le_bytes())
+            .collect();
+        self.0.write_all(&bytes)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(feature = "native-device")]
+mod device {
+    use super::Sink;
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SizedSample};
+    use std::collections::VecDeque;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    // The decoder runs ahead of the device by at most this much audio.
+    const MAX_BUFFERED: Duration = Duration::from_millis(500);
+
+    pub struct DeviceSink {
+        device: cpal::Device,
+        config: cpal::SupportedStreamConfig,
+        buffer: Arc<Mutex<VecDeque<f32>>>,
+        stream: Option<cpal::Stream>,
+    }
+
+    impl DeviceSink {
+        pub fn open(name: Option<&str>) -> io::Result<Self> {
+            let host = cpal::default_host();
+            let device = match name {
+                Some(name) => host
+                    .output_devices()
+                    .map_err(io::Error::other)?
+                    .find(|device| device.name().is_ok_and(|device| device == name)),
+                None => host.default_output_device(),
+            };
+            let device = device.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, "audio output device not found")
+            })?;
+            let config = device.default_output_config().map_err(io::Error::other)?;
+
+            Ok(DeviceSink {
+                device,
+                config,
+                buffer: Arc::new(Mutex::new(VecDeque::new())),
+                stream: None,
+            })
+        }
+
+        fn build<T>(&self) -> io::Result<cpal::Stream>
+        where
+            T: SizedSample + FromSample<f32>,
+        {
+            let buffer = self.buffer.clone();
+            self.device
+                .build_output_stream(
+                    &self.config.config(),
+                    move |data: &mut [T], _| {
+                        let mut buffer = buffer.lock().unwrap_or_else(|err| err.into_inner());
+                        for sample in data.iter_mut() {
+                            *sample = T::from_sample(buffer.pop_front().unwrap_or(0.0));
+                        }
+                    },
+                    |err| eprintln!("Audio device error: {}", err),
+                    None,
+                )
+                .map_err(io::Error::other)
+        }
+    }
+
+    impl Sink for DeviceSink {
+        fn format(&self) -> Option<(u32, u16)> {
+            Some((self.config.sample_rate().0, self.config.channels()))
+        }
+
+        fn start(&mut self, _sample_rate: u32, _channels: u16) -> io::Result<()> {
+            let stream = match self.config.sample_format() {
+                cpal::SampleFormat::F32 => self.build::<f32>()?,
+                cpal::SampleFormat::I16 => self.build::<i16>()?,
+                cpal::SampleFormat::U16 => self.build::<u16>()?,
+                cpal::SampleFormat::I32 => self.build::<i32>()?,
+                format => {
+                    let err = format!("unsupported device sample format {}", format);
+                    return Err(io::Error::other(err));
+                }
+            };
+            stream.play().map_err(io::Error::other)?;
+            self.stream = Some(stream);
+            Ok(())
+        }
+
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            let (sample_rate, channels) = (self.config.sample_rate().0, self.config.channels());
+            let max =
+                (MAX_BUFFERED.as_secs_f32() * sample_rate as f32) as usize * channels as usize;
+
+            loop {
+                let mut buffer = self.buffer.lock().unwrap_or_else(|err| err.into_inner());
+                if buffer.len() < max {
+                    buffer.extend(samples);
+                    return Ok(());
+                }
+                drop(buffer);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        fn finish(&mut self) -> io::Result<()> {
+            self.stream = None;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_file_sinks() {
+        assert_eq!(
+            "wav:out.wav".parse(),
+            Ok(SinkConfig::Wav(PathBuf::from("out.wav")))
+        );
+        assert_eq!(
+            "raw:/tmp/a:b.raw".parse(),
+            Ok(SinkConfig::Raw(PathBuf::from("/tmp/a:b.raw")))
+        );
+        assert_eq!("stdout".parse(), Ok(SinkConfig::Stdout));
+    }
+
+    #[test]
+    fn rejects_unknown_sinks() {
+        assert!("wav:".parse::<SinkConfig>().is_err());
+        assert!("flac:out.flac".parse::<SinkConfig>().is_err());
+        assert!("speakers".parse::<SinkConfig>().is_err());
+        assert_eq!(
+            "device".parse::<SinkConfig>().is_ok(),
+            cfg!(feature = "native-device")
+        );
+    }
+
+    #[test]
+    fn writes_clamped_little_endian_pcm() {
+        let mut sink = RawSink(Vec::new());
+        sink.start(44100, 1).unwrap();
+        sink.write(&[0.0, 1.0, -2.0]).unwrap();
+        sink.finish().unwrap();
+
+        let expected: Vec<u8> = [0, i16::MAX, -i16::MAX]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        assert_eq!(sink.0, expected);
+    }
+}
@@ -1,38 +1,501 @@
+use radioklw::{AudioSettings, EqPreset, Event, PlayerError, PlayerErrorCode};
+use std::collections::VecDeque;
 use std::io;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::process::{Child, ChildStderr};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+use crate::events::{Events, LEVEL_INTERVAL};
+#[cfg(feature = "native-audio")]
+use crate::pipeline::{self, Playback, SinkConfig};
+use crate::player_error;
 
 const PLAYER: &str = "ffplay";
-const KILL: &str = "kill";
 
-#[derive(Debug, Clone)]
+// Only the end of the player's output is needed to classify an error.
+const MAX_STDERR_LINES: usize = 20;
+const ERROR_LEVELS: [&str; 3] = ["[error] ", "[fatal] ", "[panic] "];
+
+// Audio below this level counts as silence.
+const SILENCE_DB: f32 = -50.0;
+
+const LEVEL_FLOOR_DB: f32 = -90.0;
+const LEVEL_KEY: &str = "lavfi.astats.Overall.RMS_level";
+
+// How long a freshly started player must stay alive before the stream is
+// considered playable and the remaining candidates are skipped.
+const STARTUP_GRACE: Duration = Duration::from_secs(2);
+
+const DEFAULT_VOLUME: u8 = 100;
+
+/// How the streams are played.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Backend {
+    #[default]
+    Ffplay,
+    /// In-process decoding, audio processing settings are not applied
+    #[cfg(feature = "native-audio")]
+    Native(SinkConfig),
+}
+
+#[derive(Debug)]
 pub struct Player {
-    pub child_process_id: Option<u32>,
+    /// Asks the task owning the running ffplay to kill it
+    kill: Option<oneshot::Sender<()>>,
+    pub volume: u8,
+    pub device: Option<String>,
+    pub server_device: Option<String>,
+    pub audio: AudioSettings,
+    #[cfg_attr(not(feature = "native-audio"), allow(dead_code))]
+    pub backend: Backend,
+    #[cfg(feature = "native-audio")]
+    playback: Option<Arc<Playback>>,
+    /// Silence after which an event is published, `None` to not detect it
+    pub silence: Option<Duration>,
+    zone: String,
+    events: Events,
+    stopping: Arc<AtomicBool>,
 }
 
 impl Player {
-    pub fn new() -> Self {
+    pub fn new(zone: &str, backend: Backend, events: Events) -> Self {
         Player {
-            child_process_id: None,
+            kill: None,
+            volume: DEFAULT_VOLUME,
+            device: None,
+            server_device: None,
+            audio: AudioSettings::default(),
+            backend,
+            #[cfg(feature = "native-audio")]
+            playback: None,
+            silence: None,
+            zone: zone.to_string(),
+            events,
+            stopping: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub async fn play(&mut self, url: Arc<String>) -> Result<(), io::Error> {
-        let child = tokio::process::Command::new(PLAYER)
-            .args(["-nodisp", "-nostats", "-loglevel", "0", &url])
-            .stdin(std::process::Stdio::null())
-            .spawn()?;
+    /// The zone's own output device, or the server-wide one.
+    pub fn output_device(&self) -> Option<&str> {
+        self.device.as_deref().or(self.server_device.as_deref())
+    }
 
-        self.child_process_id = child.id();
+    /// Tries the candidate stream URLs in order and returns the one that
+    /// started playing. Errors of the player after that, and silence if
+    /// detection is enabled, are published as events.
+    pub async fn play(&mut self, candidates: &[String]) -> Result<String, PlayerError> {
+        let mut last_error = None;
 
-        Ok(())
+        for url in candidates {
+            let monitor = AudioMonitor::new(&self.zone, &self.events, self.silence);
+
+            #[cfg(feature = "native-audio")]
+            if let Backend::Native(sink) = &self.backend {
+                let output = pipeline::Output {
+                    sink: sink.clone(),
+                    device: self.output_device().map(str::to_string),
+                    volume: self.volume,
+                };
+                match pipeline::start(url, output, monitor.clone()).await {
+                    Ok((playback, failure)) => {
+                        self.playback = Some(Arc::new(playback));
+                        self.stopping = monitor.stopping.clone();
+                        tokio::spawn(watch_pipeline(failure, monitor, url.clone()));
+                        return Ok(url.clone());
+                    }
+                    Err(err) => {
+                        let error = player_error::from_error(&err, Some(url));
+                        eprintln!("Unable to play {}: {}", url, error);
+                        last_error = Some(error);
+                        continue;
+                    }
+                }
+            }
+
+            // Info messages are needed for the levels and silencedetect, the
+            // level tags tell them apart from the errors.
+            let mut command = tokio::process::Command::new(PLAYER);
+            command
+                .args(["-nodisp", "-nostats", "-loglevel", "level+info"])
+                .args(["-volume", &self.volume.to_string()])
+                .stdin(Stdio::null())
+                .stderr(Stdio::piped());
+
+            // Players started while no client is subscribed show no level.
+            let mut filters: Vec<String> = audio_filters(&self.audio).into_iter().collect();
+            if self.events.subscribed() {
+                filters.push(format!(
+                    "astats=metadata=1:reset=1,ametadata=mode=print:key={}",
+                    LEVEL_KEY
+                ));
+            }
+            if let Some(silence) = self.silence {
+                filters.push(format!(
+                    "silencedetect=noise={}dB:d={}",
+                    SILENCE_DB,
+                    silence.as_secs_f32()
+                ));
+            }
+            if !filters.is_empty() {
+                command.args(["-af", &filters.join(",")]);
+            }
+            command.arg(url);
+
+            // SDL picks the output device from AUDIODEV, PulseAudio and
+            // PipeWire clients from PULSE_SINK.
+            if let Some(device) = self.output_device() {
+                command.env("AUDIODEV", device).env("PULSE_SINK", device);
+            }
+
+            let mut child = match command.spawn() {
+                Ok(child) => child,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    return Err(PlayerError {
+                        code: PlayerErrorCode::PlayerMissing,
+                        message: format!("{} is not installed", PLAYER),
+                        url: None,
+                    });
+                }
+                Err(err) => return Err(player_error::from_error(&err, Some(url))),
+            };
+            let stderr = tokio::spawn(read_stderr(child.stderr.take(), monitor.clone()));
+
+            if let Ok(status) = tokio::time::timeout(STARTUP_GRACE, child.wait()).await {
+                let lines = stderr.await.unwrap_or_default();
+                let error = player_error::from_output(&lines, status.ok(), url);
+                eprintln!("Unable to play {}: {}", url, error);
+                last_error = Some(error);
+                continue;
+            }
+
+            let (kill, killed) = oneshot::channel();
+            self.kill = Some(kill);
+            self.stopping = monitor.stopping.clone();
+            tokio::spawn(watch_player(child, killed, stderr, monitor, url.clone()));
+            return Ok(url.clone());
+        }
+
+        Err(last_error.unwrap_or_else(|| PlayerError {
+            code: PlayerErrorCode::Unknown,
+            message: "no playable stream found".to_string(),
+            url: None,
+        }))
     }
 
-    pub async fn stop(&self) -> Result<(), io::Error> {
-        if let Some(id) = self.child_process_id {
-            let _child = tokio::process::Command::new(KILL)
-                .args(["-INT", id.to_string().as_ref()])
-                .spawn()?;
+    pub async fn stop(&mut self) -> Result<(), io::Error> {
+        self.stopping.store(true, Ordering::Relaxed);
+        #[cfg(feature = "native-audio")]
+        if let Some(playback) = &self.playback {
+            playback.stop();
+        }
+        // The player may have exited already, nothing is left to kill then.
+        if let Some(kill) = self.kill.take() {
+            let _ = kill.send(());
         }
         Ok(())
     }
 }
+
+/// Watches a running player: reports its errors, publishes the audio level
+/// and tracks silence.
+#[derive(Debug, Clone)]
+pub struct AudioMonitor {
+    zone: String,
+    events: Events,
+    // Set when the player is stopped on purpose, so that its exit is not
+    // reported as an error.
+    stopping: Arc<AtomicBool>,
+    silence: Option<Duration>,
+    silent_for: Duration,
+    silent: bool,
+    level: Option<f32>,
+    level_sent: Instant,
+}
+
+impl AudioMonitor {
+    fn new(zone: &str, events: &Events, silence: Option<Duration>) -> Self {
+        AudioMonitor {
+            zone: zone.to_string(),
+            events: events.clone(),
+            stopping: Arc::new(AtomicBool::new(false)),
+            silence,
+            silent_for: Duration::ZERO,
+            silent: false,
+            level: None,
+            level_sent: Instant::now(),
+        }
+    }
+
+    fn publish(&self, event: Event) {
+        if !self.stopping.load(Ordering::Relaxed) {
+            self.events.publish(event);
+        }
+    }
+
+    fn report(&self, error: PlayerError) {
+        if !self.stopping.load(Ordering::Relaxed) {
+            eprintln!("Player of zone {} stopped: {}", self.zone, error);
+        }
+        let zone = self.zone.clone();
+        self.publish(Event::PlayerError { zone, error });
+    }
+
+    fn silence_started(&mut self) {
+        if let (false, Some(silence)) = (self.silent, self.silence) {
+            self.silent = true;
+            self.publish(Event::Silence {
+                zone: self.zone.clone(),
+                secs: silence.as_secs(),
+            });
+        }
+    }
+
+    fn silence_ended(&mut self) {
+        if self.silent {
+            self.silent = false;
+            self.publish(Event::SilenceEnded {
+                zone: self.zone.clone(),
+            });
+        }
+    }
+
+    /// Publishes the loudest of the levels seen every `LEVEL_INTERVAL`.
+    fn level(&mut self, db: f32) {
+        let db = db.max(LEVEL_FLOOR_DB);
+        let level = self.level.map_or(db, |level| level.max(db));
+        if self.level_sent.elapsed() < LEVEL_INTERVAL {
+            self.level = Some(level);
+            return;
+        }
+
+        self.level = None;
+        self.level_sent = Instant::now();
+        if !self.stopping.load(Ordering::Relaxed) {
+            self.events.set_level(&self.zone, level);
+        }
+    }
+
+    /// Measures the level of decoded interleaved samples and tracks silence.
+    #[cfg_attr(not(feature = "native-audio"), allow(dead_code))]
+    pub fn samples(&mut self, samples: &[f32], sample_rate: u32, channels: u16) {
+        if !samples.is_empty() && self.events.subscribed() {
+            let power = samples.iter().map(|sample| sample * sample).sum::<f32>();
+            self.level(10.0 * (power / samples.len() as f32).log10());
+        }
+
+        let Some(silence) = self.silence else {
+            return;
+        };
+
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        if peak < 10f32.powf(SILENCE_DB / 20.0) {
+            let frames = samples.len() as f64 / channels.max(1) as f64;
+            self.silent_for += Duration::from_secs_f64(frames / sample_rate.max(1) as f64);
+            if self.silent_for >= silence {
+                self.silence_started();
+            }
+        } else {
+            self.silent_for = Duration::ZERO;
+            self.silence_ended();
+        }
+    }
+}
+
+/// Forwards the levels and silencedetect messages to the monitor and returns
+/// the last error messages of the player.
+async fn read_stderr(stderr: Option<ChildStderr>, mut monitor: AudioMonitor) -> Vec<String> {
+    let mut errors = VecDeque::new();
+    let Some(stderr) = stderr else {
+        return Vec::new();
+    };
+
+    let mut reader = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = reader.next_line().await {
+        if let Some((_, db)) = line.split_once(LEVEL_KEY) {
+            // -inf for digital silence
+            if let Ok(db) = db.trim_start_matches('=').trim().parse() {
+                monitor.level(db);
+            }
+        } else if line.contains("silence_start:") {
+            monitor.silence_started();
+        } else if line.contains("silence_end:") {
+            monitor.silence_ended();
+        }
+
+        let error = ERROR_LEVELS
+            .iter()
+            .find(|level| line.contains(*level))
+            .map(|level| line.replacen(level, "", 1));
+        if let Some(error) = error {
+            if errors.len() == MAX_STDERR_LINES {
+                errors.pop_front();
+            }
+            errors.push_back(error);
+        }
+    }
+    errors.into()
+}
+
+/// Kills the player when asked to, and reports its exit unless it was
+/// stopped. The player is only signalled through its handle, so that a
+/// reaped player's process id is never reused.
+async fn watch_player(
+    mut child: Child,
+    killed: oneshot::Receiver<()>,
+    stderr: JoinHandle<Vec<String>>,
+    monitor: AudioMonitor,
+    url: String,
+) {
+    let status = tokio::select! {
+        status = child.wait() => status.ok(),
+        Ok(()) = killed => {
+            if let Err(err) = child.kill().await {
+                eprintln!("Unable to stop the player of zone {}: {}", monitor.zone, err);
+            }
+            None
+        }
+    };
+    let lines = stderr.await.unwrap_or_default();
+    monitor.report(player_error::from_output(&lines, status, &url));
+}
+
+/// Reports the error that ended a native playback unless it was stopped.
+#[cfg(feature = "native-audio")]
+async fn watch_pipeline(
+    failure: tokio::sync::oneshot::Receiver<io::Error>,
+    monitor: AudioMonitor,
+    url: String,
+) {
+    if let Ok(err) = failure.await {
+        monitor.report(player_error::from_error(&err, Some(&url)));
+    }
+}
+
+/// Builds the ffmpeg audio filter chain for the settings: equalizer, then
+/// EBU R128 loudness normalization, then the limiter.
+fn audio_filters(settings: &AudioSettings) -> Option<String> {
+    let mut filters = Vec::new();
+
+    match settings.eq {
+        EqPreset::Flat => {}
+        EqPreset::Speech => {
+            filters.push("highpass=f=120");
+            filters.push("equalizer=f=2500:t=q:w=1:g=4");
+        }
+        EqPreset::Music => filters.push("bass=g=2,treble=g=2"),
+        EqPreset::BassBoost => filters.push("bass=g=8:f=100"),
+    }
+    if settings.loudnorm {
+        filters.push("loudnorm=I=-16:TP=-1.5:LRA=11");
+    }
+    if settings.limiter {
+        filters.push("alimiter=limit=0.9");
+    }
+
+    if filters.is_empty() {
+        None
+    } else {
+        Some(filters.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(eq: EqPreset, loudnorm: bool, limiter: bool) -> AudioSettings {
+        AudioSettings {
+            loudnorm,
+            eq,
+            limiter,
+        }
+    }
+
+    #[test]
+    fn no_filters_by_default() {
+        assert_eq!(audio_filters(&AudioSettings::default()), None);
+    }
+
+    #[test]
+    fn equalizer_then_loudness_then_limiter() {
+        assert_eq!(
+            audio_filters(&settings(EqPreset::Speech, true, true)).as_deref(),
+            Some("highpass=f=120,equalizer=f=2500:t=q:w=1:g=4,loudnorm=I=-16:TP=-1.5:LRA=11,alimiter=limit=0.9")
+        );
+        assert_eq!(
+            audio_filters(&settings(EqPreset::BassBoost, false, true)).as_deref(),
+            Some("bass=g=8:f=100,alimiter=limit=0.9")
+        );
+        assert_eq!(
+            audio_filters(&settings(EqPreset::Flat, true, false)).as_deref(),
+            Some("loudnorm=I=-16:TP=-1.5:LRA=11")
+        );
+    }
+
+    /// The next event other than a level, if one comes in shortly.
+    async fn next_event(events: &mut crate::events::Subscription) -> Option<Event> {
+        let next = async {
+            loop {
+                match events.next().await.as_deref() {
+                    Some(Event::Level { .. }) => continue,
+                    event => return event.cloned(),
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_millis(100), next)
+            .await
+            .ok()
+            .flatten()
+    }
+
+    #[tokio::test]
+    async fn reports_silence_once_and_its_end() {
+        let events = Events::new();
+        let mut received = events.subscribe();
+        let mut monitor = AudioMonitor::new("kitchen", &events, Some(Duration::from_secs(2)));
+
+        // One second of stereo silence at 8 kHz per call
+        let silence = vec![0.0; 16000];
+        monitor.samples(&silence, 8000, 2);
+        assert_eq!(next_event(&mut received).await, None);
+
+        monitor.samples(&silence, 8000, 2);
+        monitor.samples(&silence, 8000, 2);
+        assert_eq!(
+            next_event(&mut received).await,
+            Some(Event::Silence {
+                zone: String::from("kitchen"),
+                secs: 2,
+            })
+        );
+        assert_eq!(next_event(&mut received).await, None);
+
+        monitor.samples(&[0.5; 16], 8000, 2);
+        assert_eq!(
+            next_event(&mut received).await,
+            Some(Event::SilenceEnded {
+                zone: String::from("kitchen"),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn quiet_noise_is_silence() {
+        let events = Events::new();
+        let mut received = events.subscribe();
+        let mut monitor = AudioMonitor::new("kitchen", &events, Some(Duration::from_secs(1)));
+
+        monitor.samples(&[0.001; 8000], 8000, 1);
+        assert!(matches!(
+            next_event(&mut received).await,
+            Some(Event::Silence { .. })
+        ));
+    }
+}
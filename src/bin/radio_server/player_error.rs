@@ -0,0 +1,166 @@
+use radioklw::{PlayerError, PlayerErrorCode};
+use std::error::Error;
+use std::process::ExitStatus;
+
+// Lowercase fragments of ffmpeg, reqwest and OS error messages, checked in
+// order so that e.g. a TLS failure is not reported as a connection failure.
+const PATTERNS: &[(&str, PlayerErrorCode)] = &[
+    ("404", PlayerErrorCode::NotFound),
+    ("not found", PlayerErrorCode::NotFound),
+    ("401", PlayerErrorCode::Forbidden),
+    ("403", PlayerErrorCode::Forbidden),
+    ("forbidden", PlayerErrorCode::Forbidden),
+    ("server returned", PlayerErrorCode::HttpError),
+    ("http status", PlayerErrorCode::HttpError),
+    ("http error", PlayerErrorCode::HttpError),
+    ("name or service not known", PlayerErrorCode::DnsFailure),
+    ("failed to resolve", PlayerErrorCode::DnsFailure),
+    ("failed to lookup address", PlayerErrorCode::DnsFailure),
+    ("name resolution", PlayerErrorCode::DnsFailure),
+    ("dns error", PlayerErrorCode::DnsFailure),
+    ("certificate", PlayerErrorCode::TlsError),
+    ("tls", PlayerErrorCode::TlsError),
+    ("ssl", PlayerErrorCode::TlsError),
+    ("handshake", PlayerErrorCode::TlsError),
+    ("timed out", PlayerErrorCode::Timeout),
+    ("timeout", PlayerErrorCode::Timeout),
+    ("connection refused", PlayerErrorCode::ConnectionFailed),
+    ("connection reset", PlayerErrorCode::ConnectionFailed),
+    ("network is unreachable", PlayerErrorCode::ConnectionFailed),
+    ("no route to host", PlayerErrorCode::ConnectionFailed),
+    ("error sending request", PlayerErrorCode::ConnectionFailed),
+    ("invalid data found", PlayerErrorCode::UnsupportedFormat),
+    ("could not find codec", PlayerErrorCode::UnsupportedFormat),
+    ("decoder not found", PlayerErrorCode::UnsupportedFormat),
+    ("unsupported", PlayerErrorCode::UnsupportedFormat),
+    ("no audio track", PlayerErrorCode::UnsupportedFormat),
+    ("stream ended", PlayerErrorCode::StreamEnded),
+    ("end of file", PlayerErrorCode::StreamEnded),
+    ("audio open failed", PlayerErrorCode::AudioDevice),
+    ("sdl_openaudio", PlayerErrorCode::AudioDevice),
+    ("audio device", PlayerErrorCode::AudioDevice),
+];
+
+fn classify(message: &str) -> PlayerErrorCode {
+    let message = message.to_lowercase();
+    PATTERNS
+        .iter()
+        .find(|(pattern, _)| message.contains(pattern))
+        .map(|(_, code)| *code)
+        .unwrap_or(PlayerErrorCode::Unknown)
+}
+
+/// Classifies the exit of the player process from its stderr output.
+pub fn from_output(stderr: &[String], status: Option<ExitStatus>, url: &str) -> PlayerError {
+    let output = stderr.join("\n");
+    let (code, message) = match stderr.last() {
+        Some(last) => (classify(&output), last.clone()),
+        None if status.is_some_and(|status| status.success()) => {
+            (PlayerErrorCode::StreamEnded, "the stream ended".to_string())
+        }
+        None => (
+            PlayerErrorCode::Unknown,
+            match status {
+                Some(status) => format!("the player exited with {}", status),
+                None => "the player exited".to_string(),
+            },
+        ),
+    };
+
+    PlayerError {
+        code,
+        message,
+        url: Some(url.to_string()),
+    }
+}
+
+/// Classifies an error, including the errors it was caused by.
+pub fn from_error(err: &(dyn Error + 'static), url: Option<&str>) -> PlayerError {
+    let mut messages = vec![err.to_string()];
+    let mut source = err.source();
+    while let Some(err) = source {
+        messages.push(err.to_string());
+        source = err.source();
+    }
+
+    PlayerError {
+        code: classify(&messages.join(": ")),
+        message: messages.join(": "),
+        url: url.map(str::to_string),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    /// An error caused by another one, like the errors of reqwest.
+    #[derive(Debug)]
+    struct Caused(&'static str, std::io::Error);
+
+    impl std::fmt::Display for Caused {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl Error for Caused {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.1)
+        }
+    }
+
+    #[test]
+    fn earlier_patterns_win() {
+        assert_eq!(
+            classify("TLS handshake failed: Connection reset by peer"),
+            PlayerErrorCode::TlsError
+        );
+        assert_eq!(
+            classify("Server returned 404 Not Found"),
+            PlayerErrorCode::NotFound
+        );
+        assert_eq!(
+            classify("Server returned 5XX Server Error reply"),
+            PlayerErrorCode::HttpError
+        );
+        assert_eq!(classify("something odd"), PlayerErrorCode::Unknown);
+    }
+
+    #[test]
+    fn player_output_is_classified_as_a_whole() {
+        let stderr = [
+            String::from("Failed to resolve hostname radio.invalid: Name or service not known"),
+            String::from("radio.invalid: Input/output error"),
+        ];
+        let error = from_output(&stderr, None, "http://radio.invalid/");
+
+        assert_eq!(error.code, PlayerErrorCode::DnsFailure);
+        assert_eq!(error.message, "radio.invalid: Input/output error");
+        assert_eq!(error.url.as_deref(), Some("http://radio.invalid/"));
+    }
+
+    #[test]
+    fn silent_exits() {
+        let ended = from_output(&[], Some(ExitStatus::from_raw(0)), "http://radio/");
+        assert_eq!(ended.code, PlayerErrorCode::StreamEnded);
+
+        let failed = from_output(&[], Some(ExitStatus::from_raw(256)), "http://radio/");
+        assert_eq!(failed.code, PlayerErrorCode::Unknown);
+        assert!(failed.message.starts_with("the player exited with"));
+    }
+
+    #[test]
+    fn errors_are_classified_with_their_causes() {
+        let err = Caused(
+            "error sending request",
+            std::io::Error::new(std::io::ErrorKind::TimedOut, "operation timed out"),
+        );
+        let error = from_error(&err, None);
+
+        assert_eq!(error.code, PlayerErrorCode::Timeout);
+        assert_eq!(error.message, "error sending request: operation timed out");
+        assert_eq!(error.url, None);
+    }
+}
@@ -0,0 +1,178 @@
+use crate::radio_api::HTTP_CLIENT;
+use crate::resolver;
+use radioklw::ProbeReport;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Probes all URLs concurrently, the reports are in the order of `urls`.
+pub async fn probe_all(urls: &[String]) -> Vec<ProbeReport> {
+    let tasks: Vec<_> = urls
+        .iter()
+        .cloned()
+        .map(|url| tokio::spawn(async move { probe(&url).await }))
+        .collect();
+
+    let mut reports = Vec::with_capacity(tasks.len());
+    for (task, url) in tasks.into_iter().zip(urls) {
+        let report = task.await.unwrap_or_else(|err| ProbeReport {
+            url: url.clone(),
+            error: Some(err.to_string()),
+            ..Default::default()
+        });
+        reports.push(report);
+    }
+    reports
+}
+
+/// Connects to the station without playing it and reports what the
+/// stream looks like up to the first received audio bytes.
+pub async fn probe(url: &str) -> ProbeReport {
+    let mut report = ProbeReport {
+        url: url.to_string(),
+        ..Default::default()
+    };
+
+    let stream_url = match resolver::resolve(url).await {
+        Ok(candidates) => candidates[0].clone(),
+        Err(err) => {
+            report.error = Some(err.to_string());
+            return report;
+        }
+    };
+    report.stream_url = Some(stream_url.clone());
+
+    let started = Instant::now();
+    let response = HTTP_CLIENT
+        .get(&stream_url)
+        .header("Icy-MetaData", "1")
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await;
+
+    let mut response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            report.error = Some(err.to_string());
+            return report;
+        }
+    };
+
+    report.reachable = true;
+    report.status = Some(response.status().as_u16());
+
+    let headers = response.headers();
+    report.content_type = header_value(headers, CONTENT_TYPE.as_str());
+    report.icy_name = header_value(headers, "icy-name");
+    report.icy_genre = header_value(headers, "icy-genre");
+    report.icy_bitrate = header_value(headers, "icy-br")
+        .and_then(|br| br.split(',').next().and_then(|br| br.trim().parse().ok()));
+
+    if !response.status().is_success() {
+        return report;
+    }
+
+    match response.chunk().await {
+        Ok(Some(_)) => {
+            report.time_to_first_byte_ms = Some(started.elapsed().as_millis() as u64);
+        }
+        Ok(None) => report.error = Some("stream ended without data".to_string()),
+        Err(err) => report.error = Some(err.to_string()),
+    }
+
+    report
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with the same response and returns its URL.
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    match socket.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buf[..read]),
+                    }
+                }
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn reports_the_stream_headers() {
+        let url = serve(
+            "HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\nicy-name: Test FM\r\nicy-genre:  \r\nicy-br: 128, 128\r\n\r\naudio",
+        )
+        .await;
+        let report = probe(&url).await;
+
+        assert_eq!(report.stream_url.as_deref(), Some(url.as_str()));
+        assert!(report.reachable);
+        assert_eq!(report.status, Some(200));
+        assert_eq!(report.content_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(report.icy_name.as_deref(), Some("Test FM"));
+        assert_eq!(report.icy_genre, None);
+        assert_eq!(report.icy_bitrate, Some(128));
+        assert!(report.time_to_first_byte_ms.is_some());
+        assert_eq!(report.error, None);
+    }
+
+    #[tokio::test]
+    async fn reports_http_errors_without_reading_the_body() {
+        let url = serve("HTTP/1.0 404 Not Found\r\n\r\nmissing").await;
+        let report = probe(&url).await;
+
+        assert!(report.reachable);
+        assert_eq!(report.status, Some(404));
+        assert_eq!(report.time_to_first_byte_ms, None);
+    }
+
+    #[tokio::test]
+    async fn reports_streams_without_data() {
+        let url = serve("HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\n\r\n").await;
+        let report = probe(&url).await;
+
+        assert_eq!(report.time_to_first_byte_ms, None);
+        assert_eq!(report.error.as_deref(), Some("stream ended without data"));
+    }
+
+    #[tokio::test]
+    async fn reports_unreachable_stations() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        drop(listener);
+        let report = probe(&url).await;
+
+        assert!(!report.reachable);
+        assert!(report.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn reports_in_the_order_of_the_urls() {
+        let urls = vec![String::from("not a url"), String::from("neither")];
+        let reports = probe_all(&urls).await;
+        let probed: Vec<&str> = reports.iter().map(|report| report.url.as_str()).collect();
+        assert_eq!(probed, ["not a url", "neither"]);
+        assert!(reports.iter().all(|report| report.error.is_some()));
+    }
+}
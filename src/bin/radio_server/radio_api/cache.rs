@@ -0,0 +1,364 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use radioklw::CacheStats;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+const CACHE_FILE: &str = "directory_cache.json";
+// The least recently used responses are dropped above these limits.
+const MAX_ENTRIES: usize = 1000;
+const MAX_BYTES: u64 = 16 * 1024 * 1024;
+const SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const FACET_LISTS: [&str; 5] = ["countries", "languages", "tags", "codecs", "states"];
+
+/// How long the responses of an endpoint are used as they are, and how much
+/// longer they are still answered while being fetched again.
+#[derive(Debug, Clone, Copy)]
+pub struct Ttl {
+    fresh: TimeDelta,
+    stale: TimeDelta,
+}
+
+/// The caching of an endpoint, `None` for the ones that must reach the
+/// directory, such as clicks and votes.
+pub fn ttl(end: &str) -> Option<Ttl> {
+    if end.starts_with("/json/stations/") {
+        Some(Ttl {
+            fresh: TimeDelta::minutes(10),
+            stale: TimeDelta::days(1),
+        })
+    } else if FACET_LISTS
+        .iter()
+        .any(|list| end.strip_prefix("/json/") == Some(list))
+    {
+        Some(Ttl {
+            fresh: TimeDelta::days(1),
+            stale: TimeDelta::days(30),
+        })
+    } else {
+        None
+    }
+}
+
+/// Identifies a request independently of the server it is sent to.
+pub fn key(end: &str, filter: Option<&str>, map: &HashMap<String, String>) -> String {
+    let params: BTreeMap<_, _> = map.iter().collect();
+    let params = serde_json::to_string(&params).unwrap_or_default();
+    match filter {
+        Some(filter) => format!("{}/{} {}", end, filter, params),
+        None => format!("{} {}", end, params),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    body: Value,
+    size: u64,
+    fetched: DateTime<Utc>,
+    used: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    /// The directory the responses came from
+    source: String,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    file: CacheFile,
+    bytes: u64,
+    hits: u64,
+    stale_hits: u64,
+    misses: u64,
+    /// Keys of the expired responses being fetched again
+    revalidating: HashSet<String>,
+    dirty: bool,
+}
+
+/// Responses of the directory kept on disk, so that repeated searches and
+/// lists are answered without asking the directory again.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    state: Arc<Mutex<State>>,
+}
+
+impl Cache {
+    /// Loads the responses cached for the directory, starting empty when
+    /// they came from another one or cannot be read.
+    pub async fn load(source: &str) -> Self {
+        let file = match tokio::fs::read_to_string(CACHE_FILE).await {
+            Ok(content) => serde_json::from_str::<CacheFile>(&content)
+                .map_err(|err| eprintln!("Ignoring the cache in {}: {}", CACHE_FILE, err))
+                .unwrap_or_default(),
+            Err(error) if error.kind() == ErrorKind::NotFound => CacheFile::default(),
+            Err(error) => {
+                eprintln!("Unable to read the cache from {}: {}", CACHE_FILE, error);
+                CacheFile::default()
+            }
+        };
+        let file = if file.source == source {
+            file
+        } else {
+            CacheFile {
+                source: source.to_string(),
+                entries: HashMap::new(),
+            }
+        };
+
+        let bytes = file.entries.values().map(|entry| entry.size).sum();
+        let cache = Cache {
+            state: Arc::new(Mutex::new(State {
+                file,
+                bytes,
+                ..Default::default()
+            })),
+        };
+        tokio::spawn(cache.clone().save_changes());
+        cache
+    }
+
+    /// Returns the cached response and whether it has expired, `None` if
+    /// there is none that can still be used.
+    pub async fn get(&self, key: &str, ttl: Ttl) -> Option<(Value, bool)> {
+        let mut state = self.state.lock().await;
+        let now = Utc::now();
+        let age = match state.file.entries.get(key) {
+            Some(entry) => now - entry.fetched,
+            None => {
+                state.misses += 1;
+                return None;
+            }
+        };
+
+        if age > ttl.fresh + ttl.stale {
+            state.remove(key);
+            state.misses += 1;
+            return None;
+        }
+
+        let stale = age > ttl.fresh;
+        if stale {
+            state.stale_hits += 1;
+        }
+        state.hits += 1;
+        let entry = state.file.entries.get_mut(key)?;
+        entry.used = now;
+        Some((entry.body.clone(), stale))
+    }
+
+    /// Marks an expired response as being fetched again, false if it
+    /// already is.
+    pub async fn revalidate(&self, key: &str) -> bool {
+        self.state.lock().await.revalidating.insert(key.to_string())
+    }
+
+    /// The expired response could not be fetched again.
+    pub async fn failed(&self, key: &str) {
+        self.state.lock().await.revalidating.remove(key);
+    }
+
+    pub async fn put(&self, key: String, body: Value) {
+        let size = body.to_string().len() as u64;
+        if size > MAX_BYTES {
+            return;
+        }
+
+        let mut state = self.state.lock().await;
+        state.revalidating.remove(&key);
+        state.remove(&key);
+        let now = Utc::now();
+        state.file.entries.insert(
+            key,
+            Entry {
+                body,
+                size,
+                fetched: now,
+                used: now,
+            },
+        );
+        state.bytes += size;
+        state.evict();
+        state.dirty = true;
+    }
+
+    pub async fn clear(&self) {
+        let mut state = self.state.lock().await;
+        state.file.entries.clear();
+        state.bytes = 0;
+        save(&state.file).await;
+        state.dirty = false;
+    }
+
+    pub async fn stats(&self) -> CacheStats {
+        let state = self.state.lock().await;
+        CacheStats {
+            entries: state.file.entries.len(),
+            bytes: state.bytes,
+            max_bytes: MAX_BYTES,
+            hits: state.hits,
+            stale_hits: state.stale_hits,
+            misses: state.misses,
+            oldest: state.file.entries.values().map(|entry| entry.fetched).min(),
+        }
+    }
+
+    /// Writes the changed cache to disk from time to time rather than after
+    /// every response.
+    async fn save_changes(self) {
+        let mut interval = tokio::time::interval(SAVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut state = self.state.lock().await;
+            if state.dirty {
+                save(&state.file).await;
+                state.dirty = false;
+            }
+        }
+    }
+}
+
+impl State {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.file.entries.remove(key) {
+            self.bytes -= entry.size;
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.file.entries.len() > MAX_ENTRIES || self.bytes > MAX_BYTES {
+            let Some(key) = self
+                .file
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            self.remove(&key);
+        }
+    }
+}
+
+async fn save(file: &CacheFile) {
+    let result = match serde_json::to_string(file) {
+        Ok(json) => tokio::fs::write(CACHE_FILE, json)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("Unable to save the cache to {}: {}", CACHE_FILE, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const TTL: Ttl = Ttl {
+        fresh: TimeDelta::minutes(10),
+        stale: TimeDelta::days(1),
+    };
+
+    /// An empty cache that is never written to disk.
+    fn empty() -> Cache {
+        Cache {
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    async fn fetched_ago(cache: &Cache, key: &str, age: TimeDelta) {
+        let mut state = cache.state.lock().await;
+        state.file.entries.get_mut(key).unwrap().fetched = Utc::now() - age;
+    }
+
+    #[test]
+    fn only_searches_and_lists_are_cached() {
+        assert!(ttl("/json/stations/search").is_some());
+        assert!(ttl("/json/countries").is_some());
+        assert!(ttl("/json/url/0000").is_none());
+        assert!(ttl("/json/vote/0000").is_none());
+        assert!(ttl("/json/countriesx").is_none());
+    }
+
+    #[test]
+    fn keys_ignore_the_parameter_order() {
+        let a = HashMap::from([
+            (String::from("name"), String::from("jazz")),
+            (String::from("limit"), String::from("20")),
+        ]);
+        let b = HashMap::from([
+            (String::from("limit"), String::from("20")),
+            (String::from("name"), String::from("jazz")),
+        ]);
+        assert_eq!(
+            key("/json/stations/search", None, &a),
+            key("/json/stations/search", None, &b)
+        );
+        assert_ne!(
+            key("/json/tags", Some("jazz"), &HashMap::new()),
+            key("/json/tags", None, &HashMap::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn answers_fresh_then_stale_then_nothing() {
+        let cache = empty();
+        assert_eq!(cache.get("k", TTL).await, None);
+
+        cache.put(String::from("k"), json!(["a"])).await;
+        assert_eq!(cache.get("k", TTL).await, Some((json!(["a"]), false)));
+
+        fetched_ago(&cache, "k", TimeDelta::hours(1)).await;
+        assert_eq!(cache.get("k", TTL).await, Some((json!(["a"]), true)));
+
+        fetched_ago(&cache, "k", TimeDelta::days(2)).await;
+        assert_eq!(cache.get("k", TTL).await, None);
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.hits, stats.stale_hits, stats.misses), (2, 1, 2));
+        assert_eq!(stats.entries, 0);
+        assert_eq!(stats.bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn revalidates_once_until_fetched_or_failed() {
+        let cache = empty();
+        assert!(cache.revalidate("k").await);
+        assert!(!cache.revalidate("k").await);
+
+        cache.failed("k").await;
+        assert!(cache.revalidate("k").await);
+
+        cache.put(String::from("k"), json!(1)).await;
+        assert!(cache.revalidate("k").await);
+    }
+
+    #[tokio::test]
+    async fn drops_the_least_recently_used() {
+        let cache = empty();
+        for index in 0..MAX_ENTRIES {
+            cache.put(index.to_string(), json!(index)).await;
+        }
+        {
+            let mut state = cache.state.lock().await;
+            state.file.entries.get_mut("1").unwrap().used = Utc::now() - TimeDelta::hours(1);
+        }
+        cache.put(String::from("new"), json!("new")).await;
+
+        let state = cache.state.lock().await;
+        assert_eq!(state.file.entries.len(), MAX_ENTRIES);
+        assert!(state.file.entries.contains_key("new"));
+        assert!(!state.file.entries.contains_key("1"));
+    }
+}
@@ -0,0 +1,234 @@
+//! The station filters of radio-browser's search, for searching stations
+//! without it.
+
+use radioklw::{Near, RadioStation, StationOrder};
+use std::collections::HashMap;
+
+/// Compares a field with a filter ignoring case, like radio-browser: it has to
+/// contain the filter text unless the filter is exact.
+pub fn matches(value: &str, wanted: &str, exact: bool) -> bool {
+    if exact {
+        value.eq_ignore_ascii_case(wanted)
+    } else {
+        value.to_lowercase().contains(&wanted.to_lowercase())
+    }
+}
+
+/// The values of a comma separated field, such as the tags.
+pub fn list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// The lowercase words of a text, for matching them separately.
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Applies the filters of radio-browser's station search.
+pub fn search<'a>(
+    stations: impl Iterator<Item = &'a RadioStation>,
+    params: &HashMap<String, String>,
+) -> Vec<RadioStation> {
+    let text = |name: &str| {
+        params
+            .get(name)
+            .map(String::as_str)
+            .filter(|text| !text.is_empty())
+    };
+    let flag = |name: &str| text(name).and_then(|flag| flag.parse::<bool>().ok());
+    let number = |name: &str| text(name).and_then(|number| number.parse::<usize>().ok());
+    let coordinate = |name: &str| text(name).and_then(|number| number.parse::<f64>().ok());
+    let near = match (
+        coordinate("geo_lat"),
+        coordinate("geo_long"),
+        coordinate("geo_distance"),
+    ) {
+        (Some(lat), Some(long), Some(meters)) => Some(Near {
+            lat,
+            long,
+            radius_km: meters / 1000.0,
+        }),
+        _ => None,
+    };
+
+    let mut found: Vec<RadioStation> = stations
+        .filter(|station| {
+            text("name")
+                .is_none_or(|name| matches(&station.name, name, flag("nameExact") == Some(true)))
+        })
+        .filter(|station| {
+            text("country").is_none_or(|country| {
+                matches(
+                    &station.country,
+                    country,
+                    flag("countryExact") == Some(true),
+                )
+            })
+        })
+        .filter(|station| {
+            text("state").is_none_or(|state| {
+                matches(&station.state, state, flag("stateExact") == Some(true))
+            })
+        })
+        .filter(|station| {
+            text("language").is_none_or(|language| {
+                let exact = flag("languageExact") == Some(true);
+                list(&station.language).any(|value| matches(value, language, exact))
+            })
+        })
+        .filter(|station| {
+            text("tag").is_none_or(|tag| {
+                let exact = flag("tagExact") == Some(true);
+                list(&station.tags).any(|value| matches(value, tag, exact))
+            })
+        })
+        .filter(|station| {
+            text("tagList").is_none_or(|tags| {
+                list(tags).all(|tag| list(&station.tags).any(|value| matches(value, tag, true)))
+            })
+        })
+        .filter(|station| {
+            text("countrycode").is_none_or(|code| station.countrycode.eq_ignore_ascii_case(code))
+        })
+        .filter(|station| {
+            text("codec").is_none_or(|codec| station.codec.eq_ignore_ascii_case(codec))
+        })
+        .filter(|station| number("bitrateMin").is_none_or(|min| station.bitrate as usize >= min))
+        .filter(|station| number("bitrateMax").is_none_or(|max| station.bitrate as usize <= max))
+        .filter(|station| {
+            flag("is_https").is_none_or(|https| station.url.starts_with("https://") == https)
+        })
+        .filter(|station| flag("has_geo_info").is_none_or(|geo| station.geo_lat.is_some() == geo))
+        .filter(|station| flag("hidebroken") != Some(true) || station.lastcheckok != 0)
+        .filter(|station| near.is_none_or(|near| near.contains(station)))
+        .cloned()
+        .collect();
+
+    if let Some(order) = text("order").and_then(|order| order.parse::<StationOrder>().ok()) {
+        found.sort_by(|a, b| order.compare(a, b));
+    }
+    if flag("reverse") == Some(true) {
+        found.reverse();
+    }
+
+    found
+        .into_iter()
+        .skip(number("offset").unwrap_or(0))
+        .take(number("limit").unwrap_or(usize::MAX))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(name: &str, tags: &str, clickcount: u32) -> RadioStation {
+        RadioStation {
+            stationuuid: name.to_lowercase(),
+            name: name.to_string(),
+            url: format!("http://{}.example/stream", name.to_lowercase()),
+            tags: tags.to_string(),
+            country: String::from("Spain"),
+            countrycode: String::from("ES"),
+            language: String::from("spanish,catalan"),
+            codec: String::from("MP3"),
+            bitrate: 128,
+            lastcheckok: 1,
+            clickcount,
+            ..Default::default()
+        }
+    }
+
+    fn stations() -> Vec<RadioStation> {
+        vec![
+            station("Jazz FM", "jazz,smooth jazz", 5),
+            station("Rock Radio", "rock,classic rock", 20),
+            station("Radio Jazzy", "jazz", 10),
+        ]
+    }
+
+    fn search_names(params: &[(&str, &str)]) -> Vec<String> {
+        let params = params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        search(stations().iter(), &params)
+            .into_iter()
+            .map(|station| station.name)
+            .collect()
+    }
+
+    #[test]
+    fn names_contain_the_text_unless_exact() {
+        assert_eq!(
+            search_names(&[("name", "JAZZ")]),
+            ["Jazz FM", "Radio Jazzy"]
+        );
+        assert_eq!(
+            search_names(&[("name", "jazz fm"), ("nameExact", "true")]),
+            ["Jazz FM"]
+        );
+        assert!(search_names(&[("name", "jazz"), ("nameExact", "true")]).is_empty());
+    }
+
+    #[test]
+    fn tags_match_single_values() {
+        assert_eq!(search_names(&[("tag", "classic")]), ["Rock Radio"]);
+        assert_eq!(
+            search_names(&[("tag", "jazz"), ("tagExact", "true")]),
+            ["Jazz FM", "Radio Jazzy"]
+        );
+        assert_eq!(
+            search_names(&[("tagList", "jazz, smooth jazz")]),
+            ["Jazz FM"]
+        );
+        assert_eq!(search_names(&[("language", "catalan")]).len(), 3);
+    }
+
+    #[test]
+    fn empty_filters_are_ignored() {
+        assert_eq!(search_names(&[("name", ""), ("countrycode", "")]).len(), 3);
+        assert!(search_names(&[("countrycode", "fr")]).is_empty());
+    }
+
+    #[test]
+    fn orders_then_pages() {
+        let params = [("order", "clickcount"), ("reverse", "true")];
+        assert_eq!(
+            search_names(&params),
+            ["Rock Radio", "Radio Jazzy", "Jazz FM"]
+        );
+        assert_eq!(
+            search_names(&[params[0], params[1], ("offset", "1"), ("limit", "1")]),
+            ["Radio Jazzy"]
+        );
+        assert_eq!(
+            search_names(&[("order", "name")]),
+            ["Jazz FM", "Radio Jazzy", "Rock Radio"]
+        );
+    }
+
+    #[test]
+    fn only_stations_within_the_radius() {
+        let mut stations = stations();
+        stations[0].geo_lat = Some(39.47);
+        stations[0].geo_long = Some(-0.38);
+        stations[1].geo_lat = Some(40.42);
+        stations[1].geo_long = Some(-3.70);
+        let params = HashMap::from([
+            (String::from("geo_lat"), String::from("39.5")),
+            (String::from("geo_long"), String::from("-0.4")),
+            (String::from("geo_distance"), String::from("50000")),
+        ]);
+
+        let found = search(stations.iter(), &params);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "Jazz FM");
+    }
+}
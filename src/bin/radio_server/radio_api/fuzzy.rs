@@ -0,0 +1,181 @@
+//! Typo-tolerant matching of the searched words with the names and tags of
+//! the stations.
+
+use super::filters::words;
+use radioklw::RadioStation;
+
+// Least share of the searched words a station has to match, from 0 to 1.
+const MIN_SCORE: f64 = 0.6;
+// Share of the votes and clicks in the ranking of the matching stations.
+const POPULARITY_WEIGHT: f64 = 0.2;
+// Matching a tag counts a bit less than matching a word of the name.
+const TAG_WEIGHT: f64 = 0.8;
+// Shorter searched words only match whole words, not their beginnings.
+const MIN_PREFIX_CHARS: usize = 3;
+
+/// Orders the stations by how well they match the search, the more popular
+/// first among similar matches, and drops the ones not matching it. Names
+/// containing the search as typed always match fully.
+pub fn rank(query: &str, stations: Vec<RadioStation>) -> Vec<RadioStation> {
+    let typed = query.trim().to_lowercase();
+    let query = words(query);
+    let mut ranked: Vec<(f64, RadioStation)> = stations
+        .into_iter()
+        .map(|station| {
+            let score = if station.name.to_lowercase().contains(&typed) {
+                1.0
+            } else {
+                text_score(&query, &station)
+            };
+            (score, station)
+        })
+        .filter(|(score, _)| *score >= MIN_SCORE)
+        .collect();
+
+    let most_popular = ranked
+        .iter()
+        .map(|(_, station)| popularity(station))
+        .fold(0.0, f64::max);
+    if most_popular > 0.0 {
+        for (score, station) in ranked.iter_mut() {
+            *score = (1.0 - POPULARITY_WEIGHT) * *score
+                + POPULARITY_WEIGHT * popularity(station) / most_popular;
+        }
+    }
+
+    ranked.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranked.into_iter().map(|(_, station)| station).collect()
+}
+
+/// How well the name and tags match the searched words, from 0 to 1.
+fn text_score(query: &[String], station: &RadioStation) -> f64 {
+    if query.is_empty() {
+        return 1.0;
+    }
+
+    let name = words(&station.name);
+    let tags = words(&station.tags);
+    let best = |searched: &str| {
+        let in_name = name.iter().map(|word| similarity(searched, word));
+        let in_tags = tags
+            .iter()
+            .map(|tag| TAG_WEIGHT * similarity(searched, tag));
+        in_name.chain(in_tags).fold(0.0, f64::max)
+    };
+    query.iter().map(|searched| best(searched)).sum::<f64>() / query.len() as f64
+}
+
+fn popularity(station: &RadioStation) -> f64 {
+    (1.0 + station.votes.max(0) as f64).ln() + (1.0 + station.clickcount as f64).ln()
+}
+
+/// Similarity of a searched word with a word of the station, from 0 to 1.
+/// A searched word also matches the beginning of a longer word, e.g. "jaz"
+/// matches "jazz".
+fn similarity(searched: &str, word: &str) -> f64 {
+    let searched: Vec<char> = searched.chars().collect();
+    let word: Vec<char> = word.chars().collect();
+
+    let whole = ratio(&searched, &word);
+    if searched.len() >= MIN_PREFIX_CHARS && word.len() > searched.len() {
+        whole.max(0.95 * ratio(&searched, &word[..searched.len()]))
+    } else {
+        whole
+    }
+}
+
+fn ratio(a: &[char], b: &[char]) -> f64 {
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    1.0 - distance(a, b) as f64 / longest as f64
+}
+
+/// Edits turning one word into the other, swapping two neighbouring
+/// characters counting as one.
+fn distance(a: &[char], b: &[char]) -> usize {
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = usize::from(a[i - 1] != b[j - 1]);
+            let mut edits = (rows[i - 1][j] + 1)
+                .min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                edits = edits.min(rows[i - 2][j - 2] + 1);
+            }
+            rows[i][j] = edits;
+        }
+    }
+
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn edits(a: &str, b: &str) -> usize {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        distance(&a, &b)
+    }
+
+    fn station(name: &str, tags: &str, clickcount: u32) -> RadioStation {
+        RadioStation {
+            name: name.to_string(),
+            tags: tags.to_string(),
+            clickcount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn distance_counts_edits() {
+        assert_eq!(edits("", ""), 0);
+        assert_eq!(edits("jazz", "jazz"), 0);
+        assert_eq!(edits("", "rock"), 4);
+        assert_eq!(edits("rock", "rok"), 1);
+        assert_eq!(edits("rock", "rack"), 1);
+        assert_eq!(edits("kitten", "sitting"), 3);
+        assert_eq!(edits("élan", "elan"), 1);
+    }
+
+    #[test]
+    fn distance_counts_swaps_once() {
+        assert_eq!(edits("radio", "raido"), 1);
+        assert_eq!(edits("ab", "ba"), 1);
+    }
+
+    #[test]
+    fn misspelled_names_match() {
+        let stations = vec![
+            station("Classic Rock Radio", "rock", 0),
+            station("Smooth Jazz", "jazz", 0),
+        ];
+        let ranked = rank("clasic rok", stations);
+        assert_eq!(ranked.len(), 1);
+        assert_eq!(ranked[0].name, "Classic Rock Radio");
+    }
+
+    #[test]
+    fn names_as_typed_always_match() {
+        let stations = vec![station("Kiss FM", "", 0), station("FM4", "", 0)];
+        assert_eq!(rank("fm", stations).len(), 2);
+    }
+
+    #[test]
+    fn popular_stations_first_among_equal_matches() {
+        let stations = vec![station("Jazz One", "", 10), station("Jazz Two", "", 1000)];
+        let ranked = rank("jazz", stations);
+        assert_eq!(ranked[0].name, "Jazz Two");
+    }
+}
@@ -0,0 +1,305 @@
+use super::station_data::ServerStats;
+use super::HTTP_CLIENT;
+use dns_lookup::{lookup_addr, lookup_host};
+use hickory_resolver::TokioAsyncResolver;
+use radioklw::utils::RadioError;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use url::Url;
+
+// radio-browser lists its API servers as SRV records of this name.
+const SRV_NAME: &str = "_api._tcp.radio-browser.info.";
+
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+const RECHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// First wait before looking for servers again while none is answering,
+// doubled after every further attempt up to the re-check interval.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A radio-browser API server and how well it has been answering.
+#[derive(Debug, Clone)]
+struct Mirror {
+    /// Base URL, e.g. https://de1.api.radio-browser.info
+    base: String,
+    /// Moving average of the response times
+    latency: Option<Duration>,
+    /// Failed requests since the last successful one
+    failures: u32,
+}
+
+/// The known radio-browser API servers, the best first.
+#[derive(Debug, Clone)]
+pub struct Mirrors {
+    /// Host whose addresses are the servers, `None` for a fixed list
+    index: Option<String>,
+    mirrors: Arc<Mutex<Vec<Mirror>>>,
+}
+
+impl Mirrors {
+    /// Discovers and checks the servers in the background, retrying until
+    /// some are found and then re-checking them from time to time.
+    pub fn discover(index: &str) -> Self {
+        Mirrors::start(Some(index.to_string()), Vec::new())
+    }
+
+    /// Uses the given servers only, e.g. a local stand-in for tests.
+    pub fn fixed(bases: &[Url]) -> Self {
+        let bases = bases
+            .iter()
+            .map(|base| base.as_str().trim_end_matches('/').to_string())
+            .collect();
+        Mirrors::start(None, bases)
+    }
+
+    fn start(index: Option<String>, bases: Vec<String>) -> Self {
+        let mirrors = Mirrors {
+            index,
+            mirrors: Arc::new(Mutex::new(bases.into_iter().map(Mirror::new).collect())),
+        };
+        tokio::spawn(mirrors.clone().recheck());
+        mirrors
+    }
+
+    /// The base URLs in the order to try them: those answering first, the
+    /// fastest of them first.
+    pub fn bases(&self) -> Vec<String> {
+        let mut mirrors = self.mirrors.lock().unwrap().clone();
+        mirrors.sort_by_key(|mirror| (mirror.failures, mirror.latency.unwrap_or(REQUEST_TIMEOUT)));
+        mirrors.into_iter().map(|mirror| mirror.base).collect()
+    }
+
+    /// Scores a request to the server, `None` if it failed.
+    pub fn record(&self, base: &str, latency: Option<Duration>) {
+        let mut mirrors = self.mirrors.lock().unwrap();
+        let Some(mirror) = mirrors.iter_mut().find(|mirror| mirror.base == base) else {
+            return;
+        };
+
+        match latency {
+            Some(latency) => {
+                mirror.failures = 0;
+                mirror.latency = Some(match mirror.latency {
+                    Some(average) => (average * 3 + latency) / 4,
+                    None => latency,
+                });
+            }
+            None => mirror.failures += 1,
+        }
+    }
+
+    /// Whether any server answered its last request.
+    pub fn available(&self) -> bool {
+        let mirrors = self.mirrors.lock().unwrap();
+        mirrors.iter().any(|mirror| mirror.failures == 0)
+    }
+
+    async fn recheck(self) {
+        let mut retry = RETRY_INTERVAL;
+        loop {
+            self.refresh().await;
+            if self.available() {
+                retry = RETRY_INTERVAL;
+                tokio::time::sleep(RECHECK_INTERVAL).await;
+            } else {
+                let index = self.index.as_deref().unwrap_or("the directory");
+                eprintln!(
+                    "No radio-browser server found for {}, retrying in {} s",
+                    index,
+                    retry.as_secs()
+                );
+                tokio::time::sleep(retry).await;
+                retry = backoff(retry);
+            }
+        }
+    }
+
+    /// Looks the servers up again and checks each of them. The known servers
+    /// are kept when none can be found, e.g. while offline.
+    async fn refresh(&self) {
+        let bases: HashSet<String> = match &self.index {
+            Some(index) => discover(index)
+                .await
+                .into_iter()
+                .map(|host| format!("https://{}", host))
+                .collect(),
+            None => HashSet::new(),
+        };
+        if !bases.is_empty() {
+            let mut mirrors = self.mirrors.lock().unwrap();
+            mirrors.retain(|mirror| bases.contains(&mirror.base));
+            for base in bases {
+                if !mirrors.iter().any(|mirror| mirror.base == base) {
+                    mirrors.push(Mirror::new(base));
+                }
+            }
+        }
+
+        let mut checks = JoinSet::new();
+        for base in self.bases() {
+            checks.spawn(async move {
+                let started = Instant::now();
+                match check_server(&base).await {
+                    Ok(()) => (base, Some(started.elapsed())),
+                    Err(err) => {
+                        eprintln!("radio-browser server {} failed the check: {}", base, err);
+                        (base, None)
+                    }
+                }
+            });
+        }
+        while let Some(Ok((base, latency))) = checks.join_next().await {
+            self.record(&base, latency);
+        }
+    }
+}
+
+/// The wait before the next attempt while no server is answering.
+fn backoff(retry: Duration) -> Duration {
+    (retry * 2).min(RECHECK_INTERVAL)
+}
+
+impl Mirror {
+    fn new(base: String) -> Self {
+        Mirror {
+            base,
+            latency: None,
+            failures: 0,
+        }
+    }
+}
+
+/// Finds the servers through the SRV records and through the addresses of
+/// the index host.
+async fn discover(index: &str) -> HashSet<String> {
+    let mut hosts = HashSet::new();
+
+    match srv_hosts().await {
+        Ok(found) => hosts.extend(found),
+        Err(err) => eprintln!("SRV lookup of {} failed: {}", SRV_NAME, err),
+    }
+
+    let index = index.to_string();
+    let ips: Vec<IpAddr> = match tokio::task::spawn_blocking(move || lookup_host(&index)).await {
+        Ok(Ok(ips)) => ips,
+        Ok(Err(err)) => {
+            eprintln!("Lookup of the radio-browser servers failed: {}", err);
+            Vec::new()
+        }
+        Err(_) => Vec::new(),
+    };
+    for ip in ips {
+        match tokio::task::spawn_blocking(move || lookup_addr(&ip)).await {
+            Ok(Ok(hostname)) => {
+                hosts.insert(hostname);
+            }
+            Ok(Err(err)) => eprintln!("Reverse lookup_addr failed for {}: {}", ip, err),
+            Err(_) => {}
+        }
+    }
+
+    hosts
+}
+
+async fn srv_hosts() -> Result<Vec<String>, RadioError> {
+    let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+    let lookup = resolver.srv_lookup(SRV_NAME).await?;
+    Ok(lookup
+        .iter()
+        .map(|srv| srv.target().to_utf8().trim_end_matches('.').to_string())
+        .collect())
+}
+
+async fn check_server(base: &str) -> Result<(), RadioError> {
+    let stats: Option<ServerStats> = HTTP_CLIENT
+        .get(format!("{}{}", base, "/json/stats"))
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?
+        .json::<Option<ServerStats>>()
+        .await?;
+
+    match stats {
+        Some(stats) if stats.status != "OK" => Err(format!("status {}", stats.status).into()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Servers that were not checked yet, without re-checking them.
+    fn unchecked(bases: &[&str]) -> Mirrors {
+        let mirrors = bases.iter().map(|base| Mirror::new(base.to_string()));
+        Mirrors {
+            index: None,
+            mirrors: Arc::new(Mutex::new(mirrors.collect())),
+        }
+    }
+
+    fn order(mirrors: &Mirrors) -> Vec<String> {
+        mirrors.bases()
+    }
+
+    #[test]
+    fn answering_servers_first_the_fastest_first() {
+        let mirrors = unchecked(&["a", "b", "c", "d"]);
+        mirrors.record("a", Some(Duration::from_millis(300)));
+        mirrors.record("b", Some(Duration::from_millis(100)));
+        mirrors.record("c", None);
+        mirrors.record("c", None);
+        mirrors.record("d", None);
+
+        assert_eq!(order(&mirrors), ["b", "a", "d", "c"]);
+    }
+
+    #[test]
+    fn latency_is_a_moving_average() {
+        let mirrors = unchecked(&["a"]);
+        mirrors.record("a", Some(Duration::from_millis(100)));
+        mirrors.record("a", Some(Duration::from_millis(500)));
+
+        let latency = mirrors.mirrors.lock().unwrap()[0].latency;
+        assert_eq!(latency, Some(Duration::from_millis(200)));
+    }
+
+    #[test]
+    fn an_answer_clears_the_failures() {
+        let mirrors = unchecked(&["a", "b"]);
+        mirrors.record("a", None);
+        mirrors.record("b", Some(Duration::from_millis(900)));
+        mirrors.record("a", Some(Duration::from_millis(100)));
+        mirrors.record("unknown", None);
+
+        assert_eq!(order(&mirrors), ["a", "b"]);
+        assert!(mirrors
+            .mirrors
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|mirror| mirror.failures == 0));
+    }
+
+    #[test]
+    fn available_while_a_server_answers() {
+        let mirrors = unchecked(&["a", "b"]);
+        mirrors.record("a", None);
+        assert!(mirrors.available());
+        mirrors.record("b", None);
+        assert!(!mirrors.available());
+        assert!(!unchecked(&[]).available());
+    }
+
+    #[test]
+    fn retries_back_off_up_to_the_recheck_interval() {
+        let waits: Vec<u64> =
+            std::iter::successors(Some(RETRY_INTERVAL), |retry| Some(backoff(*retry)))
+                .take(10)
+                .map(|wait| wait.as_secs())
+                .collect();
+        assert_eq!(waits, [5, 10, 20, 40, 80, 160, 320, 600, 600, 600]);
+    }
+}
@@ -1,6 +1,12 @@
+mod cache;
+pub mod filters;
+mod fuzzy;
+mod mirrors;
 mod search_mapper;
 mod seeker;
 pub mod station_data;
+mod station_db;
+pub use radioklw::StationOrder;
 pub use search_mapper::SearchMapper;
-pub use search_mapper::StationOrder;
 pub use seeker::Seeker;
+pub use seeker::HTTP_CLIENT;
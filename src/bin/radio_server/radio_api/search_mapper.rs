@@ -1,59 +1,28 @@
-use super::Seeker;
+use super::{filters, fuzzy, Seeker};
 use radioklw::utils::RadioError;
-use radioklw::RadioStation;
-use std::{collections::HashMap, fmt::Display};
-
-#[allow(dead_code)]
-pub enum StationOrder {
-    Name,
-    Url,
-    Homepage,
-    Favicon,
-    Tags,
-    Country,
-    State,
-    Language,
-    Votes,
-    Codec,
-    Bitrate,
-    Lastcheckok,
-    Lastchecktime,
-    Clicktimestamp,
-    Clickcount,
-    Clicktrend,
-    Changetimestamp,
-    Random,
-}
+use radioklw::{Near, RadioStation, SearchData, StationOrder};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 
-impl Display for StationOrder {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
-        match self {
-            StationOrder::Name => write!(f, "name"),
-            StationOrder::Url => write!(f, "url"),
-            StationOrder::Homepage => write!(f, "homepage"),
-            StationOrder::Favicon => write!(f, "favicon"),
-            StationOrder::Tags => write!(f, "tags"),
-            StationOrder::Country => write!(f, "country"),
-            StationOrder::State => write!(f, "state"),
-            StationOrder::Language => write!(f, "language"),
-            StationOrder::Votes => write!(f, "votes"),
-            StationOrder::Codec => write!(f, "codec"),
-            StationOrder::Bitrate => write!(f, "bitrate"),
-            StationOrder::Lastcheckok => write!(f, "lastcheckok"),
-            StationOrder::Lastchecktime => write!(f, "lastchecktime"),
-            StationOrder::Clicktimestamp => write!(f, "clicktimestamp"),
-            StationOrder::Clickcount => write!(f, "clickcount"),
-            StationOrder::Clicktrend => write!(f, "clicktrend"),
-            StationOrder::Changetimestamp => write!(f, "changetimestamp"),
-            StationOrder::Random => write!(f, "random"),
-        }
-    }
-}
+// Stations asked for per word of a fuzzy search, the most clicked first.
+// The name as typed is asked for as many as requested.
+const FUZZY_LIMIT: u32 = 100;
+// Searched words asked for separately in a fuzzy search, the longest first.
+// They are only asked for until enough stations match.
+const FUZZY_WORDS: usize = 2;
+// Words are asked for by their beginning, so that typos later in the word
+// still find them.
+const FUZZY_PREFIX_CHARS: usize = 3;
 
 #[derive(Clone, Debug)]
 pub struct SearchMapper {
     map: HashMap<String, String>,
     api: Seeker,
+    near: Option<Near>,
+    /// Sort the stations near a place by their distance
+    by_distance: bool,
+    /// Name searched for with typos allowed, the best matches first
+    fuzzy: Option<String>,
 }
 
 impl SearchMapper {
@@ -61,6 +30,9 @@ impl SearchMapper {
         SearchMapper {
             api,
             map: HashMap::new(),
+            near: None,
+            by_distance: false,
+            fuzzy: None,
         }
     }
 
@@ -69,6 +41,12 @@ impl SearchMapper {
         self
     }
 
+    pub fn name_exact(mut self, name_exact: bool) -> Self {
+        self.map
+            .insert(String::from("nameExact"), name_exact.to_string());
+        self
+    }
+
     pub fn country(mut self, country: String) -> Self {
         self.map.insert(String::from("country"), country);
         self
@@ -79,6 +57,172 @@ impl SearchMapper {
         self
     }
 
+    pub fn country_exact(mut self, country_exact: bool) -> Self {
+        self.map
+            .insert(String::from("countryExact"), country_exact.to_string());
+        self
+    }
+
+    pub fn countrycode(mut self, countrycode: String) -> Self {
+        self.map.insert(String::from("countrycode"), countrycode);
+        self
+    }
+
+    pub fn state(mut self, state: String) -> Self {
+        self.map.insert(String::from("state"), state);
+        self
+    }
+
+    pub fn state_exact(mut self, state_exact: bool) -> Self {
+        self.map
+            .insert(String::from("stateExact"), state_exact.to_string());
+        self
+    }
+
+    pub fn language_exact(mut self, language_exact: bool) -> Self {
+        self.map
+            .insert(String::from("languageExact"), language_exact.to_string());
+        self
+    }
+
+    pub fn tag(mut self, tag: String) -> Self {
+        self.map.insert(String::from("tag"), tag);
+        self
+    }
+
+    pub fn tag_exact(mut self, tag_exact: bool) -> Self {
+        self.map
+            .insert(String::from("tagExact"), tag_exact.to_string());
+        self
+    }
+
+    pub fn tag_list(mut self, tags: &[String]) -> Self {
+        self.map.insert(String::from("tagList"), tags.join(","));
+        self
+    }
+
+    pub fn codec(mut self, codec: String) -> Self {
+        self.map.insert(String::from("codec"), codec);
+        self
+    }
+
+    pub fn bitrate_min(mut self, bitrate_min: u32) -> Self {
+        self.map
+            .insert(String::from("bitrateMin"), bitrate_min.to_string());
+        self
+    }
+
+    pub fn bitrate_max(mut self, bitrate_max: u32) -> Self {
+        self.map
+            .insert(String::from("bitrateMax"), bitrate_max.to_string());
+        self
+    }
+
+    pub fn https(mut self, https: bool) -> Self {
+        self.map.insert(String::from("is_https"), https.to_string());
+        self
+    }
+
+    pub fn has_geo_info(mut self, has_geo_info: bool) -> Self {
+        self.map
+            .insert(String::from("has_geo_info"), has_geo_info.to_string());
+        self
+    }
+
+    pub fn hidebroken(mut self, hidebroken: bool) -> Self {
+        self.map
+            .insert(String::from("hidebroken"), hidebroken.to_string());
+        self
+    }
+
+    pub fn offset(mut self, offset: u32) -> Self {
+        self.map.insert(String::from("offset"), offset.to_string());
+        self
+    }
+
+    pub fn limit(mut self, limit: u32) -> Self {
+        self.map.insert(String::from("limit"), limit.to_string());
+        self
+    }
+
+    /// Only the stations within the radius, with their distance.
+    pub fn near(mut self, near: Near) -> Self {
+        self.map
+            .insert(String::from("geo_lat"), near.lat.to_string());
+        self.map
+            .insert(String::from("geo_long"), near.long.to_string());
+        let meters = (near.radius_km * 1000.0).round() as u64;
+        self.map
+            .insert(String::from("geo_distance"), meters.to_string());
+        self.near = Some(near);
+        self.has_geo_info(true)
+    }
+
+    /// Applies all the filters of a client's search.
+    pub fn search_data(self, data: &SearchData) -> Self {
+        let mut mapper = self
+            .name(data.name.clone())
+            .country(data.country.clone())
+            .language(data.language.clone());
+
+        if let Some(tag) = &data.tag {
+            mapper = mapper.tag(tag.clone());
+        }
+        if !data.tag_list.is_empty() {
+            mapper = mapper.tag_list(&data.tag_list);
+        }
+        if let Some(countrycode) = &data.countrycode {
+            mapper = mapper.countrycode(countrycode.clone());
+        }
+        if let Some(state) = &data.state {
+            mapper = mapper.state(state.clone());
+        }
+        if let Some(codec) = &data.codec {
+            mapper = mapper.codec(codec.clone());
+        }
+        if let Some(bitrate_min) = data.bitrate_min {
+            mapper = mapper.bitrate_min(bitrate_min);
+        }
+        if let Some(bitrate_max) = data.bitrate_max {
+            mapper = mapper.bitrate_max(bitrate_max);
+        }
+        if let Some(is_https) = data.is_https {
+            mapper = mapper.https(is_https);
+        }
+        if let Some(has_geo_info) = data.has_geo_info {
+            mapper = mapper.has_geo_info(has_geo_info);
+        }
+        if let Some(offset) = data.offset {
+            mapper = mapper.offset(offset);
+        }
+        if let Some(limit) = data.limit {
+            mapper = mapper.limit(limit);
+        }
+        if let Some(near) = data.near {
+            mapper = mapper.near(near);
+            mapper.by_distance = data.order.is_none();
+        }
+        // Pages after the first follow the directory's order, the ranking
+        // only covers the first results.
+        if !data.name.is_empty()
+            && !data.name_exact
+            && data.order.is_none()
+            && data.offset.is_none()
+        {
+            mapper.fuzzy = Some(data.name.clone());
+        }
+
+        mapper
+            .order(data.order.unwrap_or(StationOrder::Clickcount))
+            .reverse(data.reverse.unwrap_or(data.order.is_none()))
+            .hidebroken(data.hidebroken)
+            .name_exact(data.name_exact)
+            .country_exact(data.country_exact)
+            .language_exact(data.language_exact)
+            .tag_exact(data.tag_exact)
+            .state_exact(data.state_exact)
+    }
+
     pub fn reverse(mut self, reverse: bool) -> Self {
         self.map
             .insert(String::from("reverse"), reverse.to_string());
@@ -90,7 +234,131 @@ impl SearchMapper {
         self
     }
 
-    pub async fn send(self) -> Result<Vec<RadioStation>, RadioError> {
-        self.api.send("/json/stations/search", &self.map).await
+    pub async fn send(mut self) -> Result<Vec<RadioStation>, RadioError> {
+        // The page of the nearest stations is only known after sorting all
+        // stations within the radius.
+        let (offset, limit) = if self.by_distance {
+            (
+                number(self.map.remove("offset")).unwrap_or(0),
+                number(self.map.remove("limit")).unwrap_or(usize::MAX),
+            )
+        } else {
+            (0, usize::MAX)
+        };
+
+        let mut stations = match &self.fuzzy {
+            Some(name) => self.fuzzy_search(name).await?,
+            None => self.api.search(&self.map).await?,
+        };
+
+        if let Some(near) = self.near {
+            stations.retain(|station| near.contains(station));
+            for station in stations.iter_mut() {
+                station.distance_km = near.distance_km(station);
+            }
+            if self.by_distance {
+                stations.sort_by(|a, b| {
+                    let distance = |station: &RadioStation| station.distance_km.unwrap_or(f64::MAX);
+                    distance(a).total_cmp(&distance(b))
+                });
+            }
+        }
+
+        Ok(stations.into_iter().skip(offset).take(limit).collect())
+    }
+
+    /// Also asks for the beginning of the longest searched words in the
+    /// names, one after the other until enough stations match, so that
+    /// misspelled names still find the stations, then ranks them by how well
+    /// they match.
+    async fn fuzzy_search(&self, name: &str) -> Result<Vec<RadioStation>, RadioError> {
+        let limit = number(self.map.get("limit").cloned()).unwrap_or(usize::MAX);
+        let enough = limit.min(FUZZY_LIMIT as usize);
+
+        let mut found = Vec::new();
+        let mut seen = HashSet::new();
+        let mut ranked = Vec::new();
+        let mut last_error = None;
+        for query in fuzzy_queries(&self.map, name) {
+            match self.api.search(&query).await {
+                Ok(stations) => found.extend(
+                    stations
+                        .into_iter()
+                        .filter(|station| seen.insert(station.stationuuid.clone())),
+                ),
+                // The other queries would fail the same way.
+                Err(err) => {
+                    last_error = Some(err);
+                    break;
+                }
+            }
+
+            ranked = fuzzy::rank(name, found.clone());
+            if ranked.len() >= enough {
+                break;
+            }
+        }
+        if let (true, Some(err)) = (found.is_empty(), last_error) {
+            return Err(err);
+        }
+
+        Ok(ranked.into_iter().take(limit).collect())
+    }
+}
+
+/// The search for the name as typed, then for the beginning of each of the
+/// longest searched words in the names.
+fn fuzzy_queries(map: &HashMap<String, String>, name: &str) -> Vec<HashMap<String, String>> {
+    let mut queries = vec![map.clone()];
+
+    let mut words = filters::words(name);
+    words.sort_by_key(|word| Reverse(word.chars().count()));
+    for word in words {
+        if queries.len() > FUZZY_WORDS {
+            break;
+        }
+        let prefix: String = word.chars().take(FUZZY_PREFIX_CHARS).collect();
+        let mut by_name = map.clone();
+        by_name.insert(String::from("limit"), FUZZY_LIMIT.to_string());
+        by_name.insert(String::from("name"), prefix);
+        if !queries.contains(&by_name) {
+            queries.push(by_name);
+        }
+    }
+    queries
+}
+
+fn number(value: Option<String>) -> Option<usize> {
+    value.and_then(|value| value.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(queries: &[HashMap<String, String>]) -> Vec<&str> {
+        queries.iter().map(|query| query["name"].as_str()).collect()
+    }
+
+    #[test]
+    fn asks_for_the_longest_words_after_the_name() {
+        let map = HashMap::from([
+            (String::from("name"), String::from("radio swis jaz")),
+            (String::from("limit"), String::from("10")),
+        ]);
+        let queries = fuzzy_queries(&map, "radio swis jaz");
+
+        assert_eq!(names(&queries), ["radio swis jaz", "rad", "swi"]);
+        assert_eq!(queries[0]["limit"], "10");
+        assert_eq!(queries[1]["limit"], FUZZY_LIMIT.to_string());
+    }
+
+    #[test]
+    fn asks_for_a_beginning_once() {
+        let map = HashMap::from([(String::from("name"), String::from("jazzy jazz"))]);
+        assert_eq!(
+            names(&fuzzy_queries(&map, "jazzy jazz")),
+            ["jazzy jazz", "jaz"]
+        );
     }
 }
@@ -1,13 +1,20 @@
 use crate::radio_api::*;
-use dns_lookup::{lookup_addr, lookup_host};
+use cache::Cache;
+use mirrors::{Mirrors, REQUEST_TIMEOUT};
 use once_cell::sync::Lazy;
 use radioklw::utils::RadioError;
-use rand::prelude::SliceRandom;
-use rand::thread_rng;
-use seeker::station_data::ServerStats;
+use radioklw::{CacheStats, Facet, FacetKind, RadioStation};
 use serde::de::DeserializeOwned;
+use serde_json::Value;
+use station_data::{ActionResult, Codec, Country, Language, State, Tag};
+use station_db::StationDb;
 use std::collections::HashMap;
-use std::net::IpAddr;
+use std::time::Instant;
+use url::Url;
+
+// Mirrors tried for one request before giving up.
+const MAX_ATTEMPTS: usize = 3;
+const UNAVAILABLE: &str = "station directory unavailable";
 
 pub static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
     static APP_USER_AGENT: &str = concat!("radio_api/", env!("CARGO_PKG_VERSION"),);
@@ -19,83 +26,327 @@ pub static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
 
 #[derive(Clone, Debug)]
 pub struct Seeker {
-    server: String,
+    mirrors: Mirrors,
+    cache: Cache,
+    station_db: StationDb,
 }
 
 impl Seeker {
-    pub async fn new(dns_lookup: &str) -> Result<Self, RadioError> {
-        Ok(Seeker {
-            server: Seeker::api_server(dns_lookup).await?,
-        })
+    /// Uses the given directory servers, or the radio-browser servers
+    /// found through DNS if none are given. The servers are looked up and
+    /// checked in the background, requests fail until one answers unless
+    /// they are cached. With `station_db` all stations are downloaded and
+    /// searched locally.
+    pub async fn new(dns_lookup: &str, directories: &[Url], station_db: bool) -> Self {
+        let (mirrors, source) = if directories.is_empty() {
+            (Mirrors::discover(dns_lookup), dns_lookup.to_string())
+        } else {
+            let source: Vec<&str> = directories.iter().map(Url::as_str).collect();
+            (Mirrors::fixed(directories), source.join(" "))
+        };
+        let seeker = Seeker {
+            mirrors,
+            cache: Cache::load(&source).await,
+            station_db: StationDb::new(station_db),
+        };
+        tokio::spawn(seeker.station_db.clone().run(source, seeker.clone()));
+        seeker
+    }
+
+    /// Searches the local station database instead of the directory when it
+    /// is preferred, or when the directory cannot be reached.
+    pub async fn search(
+        &self,
+        map: &HashMap<String, String>,
+    ) -> Result<Vec<RadioStation>, RadioError> {
+        if self.station_db.preferred && !self.station_db.is_empty().await {
+            return Ok(self.station_db.search(map).await);
+        }
+
+        match self.request("/json/stations/search", None, map).await {
+            Ok(stations) => Ok(stations),
+            Err(err) if !self.station_db.is_empty().await => {
+                eprintln!("Searching the local station database: {}", err);
+                Ok(self.station_db.search(map).await)
+            }
+            Err(err) => Err(err),
+        }
     }
 
-    pub async fn send<P: DeserializeOwned>(
-        self,
+    /// Answers the request from the cache when possible. Expired responses
+    /// are still answered for a while, and fetched again in the background.
+    async fn request<P: DeserializeOwned>(
+        &self,
         end: &str,
+        filter: Option<&str>,
         map: &HashMap<String, String>,
     ) -> Result<P, RadioError> {
-        let stations = HTTP_CLIENT
-            .post(format!("https://{}{}", &self.server, end))
-            .json(map)
-            .send()
-            .await?
-            .json::<P>()
-            .await?;
+        let Some(ttl) = cache::ttl(end) else {
+            return Ok(serde_json::from_value(self.fetch(end, filter, map).await?)?);
+        };
+
+        let key = cache::key(end, filter, map);
+        let body = match self.cache.get(&key, ttl).await {
+            Some((body, false)) => body,
+            Some((body, true)) => {
+                if self.cache.revalidate(&key).await {
+                    let seeker = self.clone();
+                    let (end, filter, map) =
+                        (end.to_string(), filter.map(String::from), map.clone());
+                    tokio::spawn(async move {
+                        match seeker.fetch(&end, filter.as_deref(), &map).await {
+                            Ok(body) => seeker.cache.put(key, body).await,
+                            Err(err) => {
+                                eprintln!("Unable to refresh the cached {}: {}", end, err);
+                                seeker.cache.failed(&key).await;
+                            }
+                        }
+                    });
+                }
+                body
+            }
+            None => {
+                let body = self.fetch(end, filter, map).await?;
+                self.cache.put(key, body.clone()).await;
+                body
+            }
+        };
 
-        Ok(stations)
+        Ok(serde_json::from_value(body)?)
     }
 
-    async fn api_server(dns_lookup_info: &str) -> Result<String, RadioError> {
-        // Use spawn_blocking for DNS lookup to avoid blocking the async runtime
-        let dns_lookup_info = dns_lookup_info.to_string();
-        let mut ips: Vec<IpAddr> =
-            tokio::task::spawn_blocking(move || lookup_host(&dns_lookup_info)).await??;
+    /// Posts the request to the best mirrors in turn until one answers.
+    pub(super) async fn fetch(
+        &self,
+        end: &str,
+        filter: Option<&str>,
+        map: &HashMap<String, String>,
+    ) -> Result<Value, RadioError> {
+        self.fetch_from(end, filter, map, MAX_ATTEMPTS).await
+    }
 
-        ips.shuffle(&mut thread_rng());
+    /// Tries up to `attempts` mirrors, moving on to the next one only when
+    /// a mirror cannot be reached or fails. A request the mirror rejects
+    /// would be rejected by the others too.
+    async fn fetch_from(
+        &self,
+        end: &str,
+        filter: Option<&str>,
+        map: &HashMap<String, String>,
+        attempts: usize,
+    ) -> Result<Value, RadioError> {
+        let bases = self.mirrors.bases();
+        if bases.is_empty() {
+            return Err(UNAVAILABLE.into());
+        }
 
-        let mut host = String::new();
+        let mut last_error: RadioError = UNAVAILABLE.into();
+        for base in bases.into_iter().take(attempts) {
+            let mut url = Url::parse(&format!("{}{}", base, end))?;
+            if let (Some(filter), Ok(mut segments)) = (filter, url.path_segments_mut()) {
+                segments.push(filter);
+            }
 
-        for ip in ips {
-            // Use spawn_blocking for reverse DNS lookup
-            let ip_clone = ip;
-            host = match tokio::task::spawn_blocking(move || lookup_addr(&ip_clone)).await? {
-                Ok(hostname) => hostname,
-                Err(err) => {
-                    eprintln!("Reverse lookup_addr failed for {}: {}", ip, err);
-                    continue;
+            let started = Instant::now();
+            match post(url, map).await {
+                Ok(reply) => {
+                    self.mirrors.record(&base, Some(started.elapsed()));
+                    return Ok(reply);
                 }
-            };
-
-            match Self::check_host_connection(&host).await {
-                Ok(_) => {
-                    break;
+                Err(err) if mirror_failed(&err) => {
+                    eprintln!("Request to radio-browser server {} failed: {}", base, err);
+                    self.mirrors.record(&base, None);
+                    last_error = err.into();
                 }
                 Err(err) => {
-                    eprintln!("Unable to connect to ip: {} (host: {}): {}", ip, host, err);
+                    if err.status().is_some() {
+                        self.mirrors.record(&base, Some(started.elapsed()));
+                    }
+                    return Err(err.into());
                 }
             }
         }
-        Ok(host)
+
+        if self.mirrors.available() {
+            Err(last_error)
+        } else {
+            Err(format!("{} ({})", UNAVAILABLE, last_error).into())
+        }
     }
 
-    async fn check_host_connection(dns_lookup_info: &str) -> Result<(), RadioError> {
-        let stats: Option<ServerStats> = HTTP_CLIENT
-            .get(format!("https://{}{}", dns_lookup_info, "/json/stats"))
-            .send()
-            .await?
-            .json::<Option<ServerStats>>()
-            .await?;
+    pub async fn countries(
+        &self,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<Country>, RadioError> {
+        self.list("countries", filter, limit).await
+    }
 
-        if let Some(stats) = stats {
-            if stats.status != "OK" {
-                eprintln!("Error: server status: {}", stats.status);
-            }
+    pub async fn languages(
+        &self,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<Language>, RadioError> {
+        self.list("languages", filter, limit).await
+    }
+
+    pub async fn tags(
+        &self,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<Tag>, RadioError> {
+        self.list("tags", filter, limit).await
+    }
+
+    pub async fn codecs(
+        &self,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<Codec>, RadioError> {
+        self.list("codecs", filter, limit).await
+    }
+
+    pub async fn states(
+        &self,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<State>, RadioError> {
+        self.list("states", filter, limit).await
+    }
+
+    /// Lists the values of a filter, the ones with the most stations first.
+    pub async fn facets(
+        &self,
+        kind: FacetKind,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<Facet>, RadioError> {
+        Ok(match kind {
+            FacetKind::Countries => into_facets(self.countries(filter, limit).await?),
+            FacetKind::Languages => into_facets(self.languages(filter, limit).await?),
+            FacetKind::Tags => into_facets(self.tags(filter, limit).await?),
+            FacetKind::Codecs => into_facets(self.codecs(filter, limit).await?),
+            FacetKind::States => into_facets(self.states(filter, limit).await?),
+        })
+    }
+
+    async fn list<P: DeserializeOwned>(
+        &self,
+        endpoint: &str,
+        filter: Option<&str>,
+        limit: Option<u32>,
+    ) -> Result<Vec<P>, RadioError> {
+        let mut map = HashMap::from([
+            (String::from("order"), String::from("stationcount")),
+            (String::from("reverse"), true.to_string()),
+            (String::from("hidebroken"), true.to_string()),
+        ]);
+        if let Some(limit) = limit {
+            map.insert(String::from("limit"), limit.to_string());
         }
 
-        Ok(())
+        self.request(&format!("/json/{}", endpoint), filter, &map)
+            .await
+    }
+
+    /// Counts a play of the station in the rankings.
+    pub async fn click(&self, stationuuid: &str) -> Result<ActionResult, RadioError> {
+        self.action(&format!("/json/url/{}", stationuuid)).await
+    }
+
+    pub async fn vote(&self, stationuuid: &str) -> Result<ActionResult, RadioError> {
+        self.action(&format!("/json/vote/{}", stationuuid)).await
+    }
+
+    /// Clicks and votes count every time they arrive, so they are sent to
+    /// the best mirror only, never again to another one.
+    async fn action(&self, end: &str) -> Result<ActionResult, RadioError> {
+        let body = self.fetch_from(end, None, &HashMap::new(), 1).await?;
+        Ok(serde_json::from_value(body)?)
+    }
+
+    pub async fn cache_stats(&self) -> CacheStats {
+        self.cache.stats().await
+    }
+
+    pub async fn clear_cache(&self) -> CacheStats {
+        self.cache.clear().await;
+        self.cache.stats().await
     }
 
     pub fn get_radio_station_search_mapper(&self) -> SearchMapper {
         SearchMapper::new(self.clone())
     }
 }
+
+async fn post(url: Url, map: &HashMap<String, String>) -> reqwest::Result<Value> {
+    let reply = HTTP_CLIENT
+        .post(url)
+        .json(map)
+        .timeout(REQUEST_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+
+    Ok(reply)
+}
+
+/// Whether the mirror could not be reached or failed to answer, rather
+/// than rejecting the request. Broken or cut off replies count as failures.
+fn mirror_failed(err: &reqwest::Error) -> bool {
+    err.is_connect()
+        || err.is_timeout()
+        || err.is_decode()
+        || err.is_body()
+        || err.status().is_some_and(|status| status.is_server_error())
+}
+
+fn into_facets<P: Into<Facet>>(list: Vec<P>) -> Vec<Facet> {
+    list.into_iter().map(Into::into).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers a request with the response and returns the error of posting
+    /// to it.
+    async fn post_error(response: &'static str) -> reqwest::Error {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/json/stations",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await;
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+        post(url, &HashMap::new()).await.unwrap_err()
+    }
+
+    #[tokio::test]
+    async fn broken_replies_fail_the_mirror() {
+        let server_error =
+            post_error("HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n");
+        assert!(mirror_failed(&server_error.await));
+
+        let not_json = post_error("HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nnot json!");
+        assert!(mirror_failed(&not_json.await));
+
+        let cut_off = post_error("HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n[{\"name\":");
+        assert!(mirror_failed(&cut_off.await));
+    }
+
+    #[tokio::test]
+    async fn rejected_requests_do_not_fail_the_mirror() {
+        let not_found = post_error("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n").await;
+        assert!(!mirror_failed(&not_found));
+    }
+}
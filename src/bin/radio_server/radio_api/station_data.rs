@@ -1,3 +1,5 @@
+use radioklw::Facet;
+
 #[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
 pub struct ServerStats {
     pub supported_version: i64,
@@ -11,3 +13,153 @@ pub struct ServerStats {
     pub languages: i64,
     pub countries: i64,
 }
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+pub struct Country {
+    pub name: String,
+    pub iso_3166_1: String,
+    pub stationcount: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+pub struct Language {
+    pub name: String,
+    pub iso_639: Option<String>,
+    pub stationcount: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub stationcount: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+pub struct Codec {
+    pub name: String,
+    pub stationcount: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+pub struct State {
+    pub name: String,
+    pub country: String,
+    pub stationcount: u32,
+}
+
+impl From<Country> for Facet {
+    fn from(country: Country) -> Self {
+        Facet {
+            name: country.name,
+            code: Some(country.iso_3166_1).filter(|code| !code.is_empty()),
+            country: None,
+            stationcount: country.stationcount,
+        }
+    }
+}
+
+impl From<Language> for Facet {
+    fn from(language: Language) -> Self {
+        Facet {
+            name: language.name,
+            code: language.iso_639.filter(|code| !code.is_empty()),
+            country: None,
+            stationcount: language.stationcount,
+        }
+    }
+}
+
+impl From<Tag> for Facet {
+    fn from(tag: Tag) -> Self {
+        Facet {
+            name: tag.name,
+            code: None,
+            country: None,
+            stationcount: tag.stationcount,
+        }
+    }
+}
+
+impl From<Codec> for Facet {
+    fn from(codec: Codec) -> Self {
+        Facet {
+            name: codec.name,
+            code: None,
+            country: None,
+            stationcount: codec.stationcount,
+        }
+    }
+}
+
+impl From<State> for Facet {
+    fn from(state: State) -> Self {
+        Facet {
+            name: state.name,
+            code: None,
+            country: Some(state.country),
+            stationcount: state.stationcount,
+        }
+    }
+}
+
+/// Reply of the click and vote endpoints.
+#[derive(Default, Debug, Clone, PartialEq, serde_derive::Deserialize)]
+pub struct ActionResult {
+    pub ok: bool,
+    pub message: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn countries_and_languages_keep_their_codes() {
+        let countries: Vec<Country> =
+            serde_json::from_str(r#"[{"name": "Germany", "iso_3166_1": "DE", "stationcount": 3}]"#)
+                .unwrap();
+        let facet = Facet::from(countries[0].clone());
+        assert_eq!(facet.code.as_deref(), Some("DE"));
+        assert_eq!(facet.stationcount, 3);
+
+        let language = Language {
+            name: String::from("german"),
+            iso_639: Some(String::from("de")),
+            stationcount: 2,
+        };
+        assert_eq!(Facet::from(language).code.as_deref(), Some("de"));
+    }
+
+    #[test]
+    fn empty_codes_are_left_out() {
+        let country = Country {
+            name: String::from("Unknown"),
+            ..Default::default()
+        };
+        assert_eq!(Facet::from(country).code, None);
+
+        let language = Language {
+            iso_639: Some(String::new()),
+            ..Default::default()
+        };
+        assert_eq!(Facet::from(language).code, None);
+    }
+
+    #[test]
+    fn states_name_their_country() {
+        let state = State {
+            name: String::from("Bavaria"),
+            country: String::from("Germany"),
+            stationcount: 5,
+        };
+        assert_eq!(
+            Facet::from(state),
+            Facet {
+                name: String::from("Bavaria"),
+                code: None,
+                country: Some(String::from("Germany")),
+                stationcount: 5,
+            }
+        );
+    }
+}
@@ -0,0 +1,292 @@
+use super::filters;
+use super::Seeker;
+use chrono::{DateTime, TimeDelta, Utc};
+use radioklw::utils::RadioError;
+use radioklw::RadioStation;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+
+const DB_FILE: &str = "station_db.json";
+// Stations fetched per request, while downloading all and the changed ones.
+const PAGE_SIZE: usize = 2000;
+const UPDATE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+// Deleted stations only disappear with a new download of all stations.
+const FULL_DOWNLOAD_AGE: TimeDelta = TimeDelta::days(7);
+
+// The search parameters looked up in the word index.
+const TEXT_PARAMS: [&str; 5] = ["name", "country", "language", "tag", "tagList"];
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DbFile {
+    /// The directory the stations came from
+    source: String,
+    /// When all stations were last downloaded
+    downloaded: Option<DateTime<Utc>>,
+    stations: Vec<RadioStation>,
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    file: DbFile,
+    /// Stations by the words of their name, tags, country and language
+    words: BTreeMap<String, Vec<usize>>,
+}
+
+/// All stations of the directory kept on disk and searched locally, so that
+/// search is instant and works offline.
+#[derive(Debug, Clone)]
+pub struct StationDb {
+    index: Arc<RwLock<Index>>,
+    /// Search it instead of the directory, and keep it up to date
+    pub preferred: bool,
+}
+
+impl StationDb {
+    pub fn new(preferred: bool) -> Self {
+        StationDb {
+            index: Arc::new(RwLock::new(Index::default())),
+            preferred,
+        }
+    }
+
+    /// Loads the stations saved for the directory, then keeps them up to
+    /// date when preferred, as long as the server runs. Otherwise the saved
+    /// stations are only searched when the directory cannot be reached, and
+    /// there are none unless the server ran with the database before.
+    pub async fn run(self, source: String, seeker: Seeker) {
+        self.load(source).await;
+        if !self.preferred {
+            return;
+        }
+
+        loop {
+            let delay = match self.update(&seeker).await {
+                Ok(()) => UPDATE_INTERVAL,
+                Err(err) => {
+                    eprintln!("Unable to update the station database: {}", err);
+                    RETRY_INTERVAL
+                }
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    async fn load(&self, source: String) {
+        let file = match tokio::fs::read_to_string(DB_FILE).await {
+            Ok(content) => serde_json::from_str::<DbFile>(&content)
+                .map_err(|err| eprintln!("Ignoring the stations in {}: {}", DB_FILE, err))
+                .unwrap_or_default(),
+            Err(error) if error.kind() == ErrorKind::NotFound => DbFile::default(),
+            Err(error) => {
+                eprintln!("Unable to read the stations from {}: {}", DB_FILE, error);
+                DbFile::default()
+            }
+        };
+
+        let file = if file.source == source {
+            file
+        } else {
+            DbFile {
+                source,
+                ..Default::default()
+            }
+        };
+        *self.index.write().await = Index::new(file);
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.index.read().await.file.stations.is_empty()
+    }
+
+    /// Searches the stations with the parameters of radio-browser's search.
+    /// Text filters match the beginnings of words.
+    pub async fn search(&self, params: &HashMap<String, String>) -> Vec<RadioStation> {
+        let index = self.index.read().await;
+        let words: Vec<String> = TEXT_PARAMS
+            .iter()
+            .filter_map(|param| params.get(*param))
+            .flat_map(|text| filters::words(text))
+            .collect();
+
+        if words.is_empty() {
+            return filters::search(index.file.stations.iter(), params);
+        }
+
+        let mut candidates: Option<HashSet<usize>> = None;
+        for word in words {
+            let found: HashSet<usize> = index
+                .words
+                .range(word.clone()..)
+                .take_while(|(indexed, _)| indexed.starts_with(&word))
+                .flat_map(|(_, stations)| stations.iter().copied())
+                .collect();
+            candidates = Some(match candidates {
+                Some(candidates) => &candidates & &found,
+                None => found,
+            });
+        }
+
+        let mut candidates: Vec<usize> = candidates.unwrap_or_default().into_iter().collect();
+        candidates.sort_unstable();
+        let stations = candidates.into_iter().map(|i| &index.file.stations[i]);
+        filters::search(stations, params)
+    }
+
+    /// Downloads all stations when they are missing or old, otherwise just
+    /// the ones changed since the last update.
+    async fn update(&self, seeker: &Seeker) -> Result<(), RadioError> {
+        let (downloaded, latest) = {
+            let index = self.index.read().await;
+            (index.file.downloaded, index.latest_change())
+        };
+
+        let stations = match (downloaded, latest) {
+            (Some(downloaded), Some(latest)) if Utc::now() - downloaded < FULL_DOWNLOAD_AGE => {
+                let changed = changed_since(seeker, latest).await?;
+                if changed.is_empty() {
+                    return Ok(());
+                }
+                eprintln!("Updating {} changed stations", changed.len());
+
+                let index = self.index.read().await;
+                let mut stations = index.file.stations.clone();
+                let positions: HashMap<String, usize> = stations
+                    .iter()
+                    .enumerate()
+                    .map(|(i, station)| (station.stationuuid.clone(), i))
+                    .collect();
+                for station in changed {
+                    match positions.get(&station.stationuuid) {
+                        Some(&i) => stations[i] = station,
+                        None => stations.push(station),
+                    }
+                }
+                stations
+            }
+            _ => {
+                eprintln!("Downloading all stations for the local station database");
+                let stations = download_all(seeker).await?;
+                self.index.write().await.file.downloaded = Some(Utc::now());
+                stations
+            }
+        };
+
+        let file = {
+            let index = self.index.read().await;
+            DbFile {
+                source: index.file.source.clone(),
+                downloaded: index.file.downloaded,
+                stations,
+            }
+        };
+        eprintln!("Station database has {} stations", file.stations.len());
+        // Written before the index takes the stations, so that searches
+        // are not blocked meanwhile.
+        save(&file).await;
+        *self.index.write().await = Index::new(file);
+        Ok(())
+    }
+}
+
+impl Index {
+    fn new(file: DbFile) -> Self {
+        let mut words: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (i, station) in file.stations.iter().enumerate() {
+            let fields = [
+                &station.name,
+                &station.tags,
+                &station.country,
+                &station.language,
+            ];
+            for word in fields.into_iter().flat_map(|field| filters::words(field)) {
+                let stations = words.entry(word).or_default();
+                if stations.last() != Some(&i) {
+                    stations.push(i);
+                }
+            }
+        }
+
+        Index { file, words }
+    }
+
+    fn latest_change(&self) -> Option<DateTime<Utc>> {
+        self.file.stations.iter().filter_map(change_time).max()
+    }
+}
+
+fn change_time(station: &RadioStation) -> Option<DateTime<Utc>> {
+    let time = station.lastchangetime_iso8601.as_deref()?;
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+async fn download_all(seeker: &Seeker) -> Result<Vec<RadioStation>, RadioError> {
+    let mut stations = Vec::new();
+    loop {
+        let page = page(seeker, "/json/stations", &[], stations.len()).await?;
+        let last = page.len() < PAGE_SIZE;
+        stations.extend(page);
+        if last {
+            return Ok(stations);
+        }
+    }
+}
+
+/// The stations changed after the given time, the latest changes first.
+async fn changed_since(
+    seeker: &Seeker,
+    since: DateTime<Utc>,
+) -> Result<Vec<RadioStation>, RadioError> {
+    let order = [("order", "changetimestamp"), ("reverse", "true")];
+    let mut changed = Vec::new();
+    loop {
+        let page = page(seeker, "/json/stations/search", &order, changed.len()).await?;
+        let last = page.len() < PAGE_SIZE;
+        for station in page {
+            if change_time(&station).is_none_or(|time| time <= since) {
+                return Ok(changed);
+            }
+            changed.push(station);
+        }
+        if last {
+            return Ok(changed);
+        }
+    }
+}
+
+async fn page(
+    seeker: &Seeker,
+    end: &str,
+    params: &[(&str, &str)],
+    offset: usize,
+) -> Result<Vec<RadioStation>, RadioError> {
+    let mut map: HashMap<String, String> = params
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+    map.insert(String::from("hidebroken"), false.to_string());
+    map.insert(String::from("offset"), offset.to_string());
+    map.insert(String::from("limit"), PAGE_SIZE.to_string());
+
+    let body = seeker.fetch(end, None, &map).await?;
+    Ok(serde_json::from_value(body)?)
+}
+
+async fn save(file: &DbFile) {
+    let result = match serde_json::to_string(file) {
+        Ok(json) => tokio::fs::write(DB_FILE, json)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!("Unable to save the stations to {}: {}", DB_FILE, err);
+    }
+}
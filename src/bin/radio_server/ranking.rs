@@ -0,0 +1,107 @@
+use radioklw::RadioStation;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::radio_api::Seeker;
+
+// radio-browser counts one click per station and address a day and one vote
+// per station and address every 10 minutes, more only add load.
+const CLICK_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+const VOTE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Reports clicks and votes to radio-browser's community rankings.
+#[derive(Debug, Clone, Default)]
+pub struct Ranking {
+    clicks: Arc<Mutex<HashMap<String, Instant>>>,
+    votes: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Ranking {
+    /// Counts a play of the station, in the background. A click that was
+    /// not counted is sent again on the next play.
+    pub async fn click(&self, seeker: &Seeker, station: &RadioStation) {
+        let last = self.clicks.lock().await.get(&station.stationuuid).copied();
+        if station.stationuuid.is_empty()
+            || last.is_some_and(|last| last.elapsed() < CLICK_INTERVAL)
+        {
+            return;
+        }
+
+        let seeker = seeker.clone();
+        let clicks = self.clicks.clone();
+        let uuid = station.stationuuid.clone();
+        tokio::spawn(async move {
+            match seeker.click(&uuid).await {
+                Ok(result) if result.ok => record(&clicks, &uuid, CLICK_INTERVAL).await,
+                Ok(result) => eprintln!("Click for {} not counted: {}", uuid, result.message),
+                Err(err) => eprintln!("Unable to send the click for {}: {}", uuid, err),
+            }
+        });
+    }
+
+    pub async fn vote(&self, seeker: &Seeker, station: &RadioStation) -> Result<(), String> {
+        if station.stationuuid.is_empty() {
+            return Err(format!("{} is not a radio-browser station", station.name));
+        }
+
+        let last = self.votes.lock().await.get(&station.stationuuid).copied();
+        if let Some(wait) = last.and_then(|last| VOTE_INTERVAL.checked_sub(last.elapsed())) {
+            return Err(format!(
+                "Already voted for {}, vote again in {} min",
+                station.name,
+                wait.as_secs().div_ceil(60)
+            ));
+        }
+
+        match seeker.vote(&station.stationuuid).await {
+            Ok(result) if result.ok => {
+                record(&self.votes, &station.stationuuid, VOTE_INTERVAL).await;
+                Ok(())
+            }
+            Ok(result) => Err(format!(
+                "Vote for {} refused: {}",
+                station.name, result.message
+            )),
+            Err(err) => Err(format!("Unable to vote for {}: {}", station.name, err)),
+        }
+    }
+}
+
+/// Notes the time of an action on the station, and forgets the ones older
+/// than the interval.
+async fn record(actions: &Mutex<HashMap<String, Instant>>, stationuuid: &str, interval: Duration) {
+    let mut actions = actions.lock().await;
+    actions.retain(|_, time| time.elapsed() < interval);
+    actions.insert(stationuuid.to_string(), Instant::now());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn forgets_actions_older_than_the_interval() {
+        let actions = Mutex::new(HashMap::new());
+        let interval = Duration::from_millis(20);
+
+        record(&actions, "a", interval).await;
+        tokio::time::sleep(interval * 2).await;
+        record(&actions, "b", interval).await;
+
+        let actions = actions.lock().await;
+        assert!(!actions.contains_key("a"));
+        assert!(actions.contains_key("b"));
+    }
+
+    #[tokio::test]
+    async fn records_the_latest_action() {
+        let actions = Mutex::new(HashMap::new());
+        record(&actions, "a", VOTE_INTERVAL).await;
+        let first = actions.lock().await["a"];
+        record(&actions, "a", VOTE_INTERVAL).await;
+        assert!(actions.lock().await["a"] >= first);
+        assert_eq!(actions.lock().await.len(), 1);
+    }
+}
@@ -0,0 +1,620 @@
+use bytes::Bytes;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use radioklw::utils::RadioResult;
+use radioklw::{PlayerError, PlayerErrorCode};
+use reqwest::header::CONTENT_TYPE;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::icy::{self, IcyDemuxer};
+use crate::player_error;
+use crate::radio_api::HTTP_CLIENT;
+use crate::zone::DEFAULT_ZONE;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const CHANNEL_CAPACITY: usize = 256;
+const LISTENER_METAINT: usize = 16000;
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+// Zone names are escaped in the relay URLs except for the unreserved characters.
+const ZONE_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+const PASSTHROUGH_HEADERS: [&str; 6] = [
+    "content-type",
+    "icy-name",
+    "icy-genre",
+    "icy-br",
+    "icy-description",
+    "icy-url",
+];
+
+/// The last minutes of a stream, kept for pausing and rewinding. Chunks
+/// are addressed by their byte offset from the start of the stream.
+#[derive(Debug)]
+struct TimeshiftBuffer {
+    window: Duration,
+    chunks: VecDeque<(u64, Instant, Bytes)>,
+    end: u64,
+}
+
+impl TimeshiftBuffer {
+    fn new(window: Duration) -> Self {
+        TimeshiftBuffer {
+            window,
+            chunks: VecDeque::new(),
+            end: 0,
+        }
+    }
+
+    fn push(&mut self, data: Bytes) {
+        if self.window.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let len = data.len() as u64;
+        self.chunks.push_back((self.end, now, data));
+        self.end += len;
+
+        while let Some((_, arrived, _)) = self.chunks.front() {
+            if now.duration_since(*arrived) <= self.window {
+                break;
+            }
+            self.chunks.pop_front();
+        }
+    }
+
+    fn buffered(&self) -> Duration {
+        self.chunks
+            .front()
+            .map(|(_, arrived, _)| arrived.elapsed())
+            .unwrap_or_default()
+    }
+
+    fn offset_at(&self, delay: Duration) -> Option<u64> {
+        let target = Instant::now().checked_sub(delay);
+        self.chunks
+            .iter()
+            .find(|(_, arrived, _)| target.is_none_or(|target| *arrived >= target))
+            .or(self.chunks.back())
+            .map(|(offset, _, _)| *offset)
+    }
+
+    /// Returns the chunks from `offset` on, or from the oldest chunk if
+    /// `offset` is no longer buffered, and the offset following them.
+    fn chunks_from(&self, offset: u64) -> (Vec<Bytes>, u64) {
+        let chunks = self
+            .chunks
+            .iter()
+            .filter(|(start, _, _)| *start >= offset)
+            .map(|(_, _, data)| data.clone())
+            .collect();
+        (chunks, self.end)
+    }
+}
+
+/// A single upstream connection to a station whose audio is shared by
+/// the local player and every relay listener. Only the task reading the
+/// stream holds the sender, so that the listeners see the end of the stream.
+#[derive(Debug)]
+pub struct StreamTap {
+    pub headers: Vec<(String, String)>,
+    audio: broadcast::WeakSender<Bytes>,
+    title: watch::Receiver<Option<String>>,
+    ended: watch::Receiver<Option<PlayerError>>,
+    timeshift: Arc<StdMutex<TimeshiftBuffer>>,
+    task: JoinHandle<()>,
+}
+
+impl StreamTap {
+    /// Connects to the stream and keeps the last `timeshift` of it. Returns
+    /// `None` for streams that cannot be relayed byte by byte, such as HLS
+    /// playlists, or joined in the middle, such as WAV streams.
+    pub async fn open(url: &str, timeshift: Duration) -> RadioResult<Option<Arc<StreamTap>>> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Ok(None);
+        }
+
+        // A timeout of the request would also end the endless body.
+        let request = HTTP_CLIENT.get(url).header("Icy-MetaData", "1").send();
+        let mut response = tokio::time::timeout(CONNECT_TIMEOUT, request)
+            .await
+            .map_err(|_| "timed out connecting to the stream")??
+            .error_for_status()?;
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        if content_type.contains("mpegurl")
+            || content_type.contains("wav")
+            || content_type.starts_with("text/html")
+        {
+            return Ok(None);
+        }
+
+        let headers = PASSTHROUGH_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = response.headers().get(*name)?.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        let metaint = response
+            .headers()
+            .get("icy-metaint")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<usize>().ok())
+            .filter(|metaint| *metaint > 0);
+
+        // The first chunk proves that audio is flowing before the player
+        // is pointed at the relay.
+        let first = tokio::time::timeout(CONNECT_TIMEOUT, response.chunk())
+            .await
+            .map_err(|_| "no data received from the stream")??
+            .ok_or("stream ended without data")?;
+
+        let (task_audio, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let audio = task_audio.downgrade();
+        let (title_tx, title) = watch::channel(None);
+        let (ended_tx, ended) = watch::channel(None);
+        let timeshift = Arc::new(StdMutex::new(TimeshiftBuffer::new(timeshift)));
+        let task_timeshift = timeshift.clone();
+        let stream_url = url.to_string();
+
+        let task = tokio::spawn(async move {
+            let mut demuxer = IcyDemuxer::new(metaint);
+            let mut data = first;
+
+            let error = loop {
+                let mut samples = Vec::with_capacity(data.len());
+                for meta in demuxer.push(&data, &mut samples) {
+                    if let Some(title) = icy::stream_title(&meta) {
+                        let title = Some(title).filter(|title| !title.is_empty());
+                        title_tx.send_if_modified(|current| {
+                            let changed = *current != title;
+                            *current = title;
+                            changed
+                        });
+                    }
+                }
+                if !samples.is_empty() {
+                    let samples = Bytes::from(samples);
+                    if let Ok(mut timeshift) = task_timeshift.lock() {
+                        timeshift.push(samples.clone());
+                    }
+                    let _ = task_audio.send(samples);
+                }
+
+                data = match response.chunk().await {
+                    Ok(Some(data)) => data,
+                    Ok(None) => {
+                        break PlayerError {
+                            code: PlayerErrorCode::StreamEnded,
+                            message: "the stream ended".to_string(),
+                            url: Some(stream_url),
+                        }
+                    }
+                    Err(err) => break player_error::from_error(&err, Some(&stream_url)),
+                };
+            };
+            let _ = ended_tx.send(Some(error));
+        });
+
+        Ok(Some(Arc::new(StreamTap {
+            headers,
+            audio,
+            title,
+            ended,
+            timeshift,
+            task,
+        })))
+    }
+
+    /// How far back the stream is buffered.
+    pub fn buffered(&self) -> Duration {
+        self.timeshift
+            .lock()
+            .map(|timeshift| timeshift.buffered())
+            .unwrap_or_default()
+    }
+
+    /// Offset of the buffered audio received `delay` ago.
+    pub fn offset_at(&self, delay: Duration) -> Option<u64> {
+        self.timeshift
+            .lock()
+            .ok()
+            .and_then(|timeshift| timeshift.offset_at(delay))
+    }
+
+    /// Returns `None` once the stream has ended.
+    pub fn subscribe(&self) -> Option<broadcast::Receiver<Bytes>> {
+        self.audio.upgrade().map(|audio| audio.subscribe())
+    }
+
+    pub fn title(&self) -> watch::Receiver<Option<String>> {
+        self.title.clone()
+    }
+
+    /// Waits until the stream ends by itself and returns why, or `None`
+    /// once the tap is closed.
+    pub async fn ended(&self) -> Option<PlayerError> {
+        let mut ended = self.ended.clone();
+        let error = ended.wait_for(Option::is_some).await.ok()?;
+        error.clone()
+    }
+
+    pub fn close(&self) {
+        self.task.abort();
+    }
+}
+
+impl Drop for StreamTap {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Where a relay listener reads the audio from.
+enum Source {
+    Live(broadcast::Receiver<Bytes>),
+    Shifted {
+        timeshift: Arc<StdMutex<TimeshiftBuffer>>,
+        next: u64,
+        pending: VecDeque<Bytes>,
+        wakeup: broadcast::Receiver<Bytes>,
+    },
+}
+
+impl Source {
+    async fn next(&mut self) -> Option<Bytes> {
+        match self {
+            Source::Live(audio) => loop {
+                match audio.recv().await {
+                    Ok(data) => return Some(data),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            },
+            Source::Shifted {
+                timeshift,
+                next,
+                pending,
+                wakeup,
+            } => loop {
+                if let Some(data) = pending.pop_front() {
+                    return Some(data);
+                }
+
+                let (chunks, end) = timeshift.lock().ok()?.chunks_from(*next);
+                if chunks.is_empty() {
+                    // New audio is broadcast right after it was buffered.
+                    if let Err(broadcast::error::RecvError::Closed) = wakeup.recv().await {
+                        return None;
+                    }
+                } else {
+                    pending.extend(chunks);
+                    *next = end;
+                }
+            },
+        }
+    }
+}
+
+/// Serves the stream of every playing zone over plain HTTP, the default
+/// zone at `/` and named zones at `/<zone>`. A `from=<offset>` query serves
+/// the stream from the timeshift buffer instead of live.
+#[derive(Debug, Clone)]
+pub struct Relay {
+    pub addr: SocketAddr,
+    pub timeshift: Duration,
+    taps: Arc<Mutex<HashMap<String, Arc<StreamTap>>>>,
+}
+
+impl Relay {
+    pub async fn bind(addr: &str, timeshift: Duration) -> RadioResult<(Self, TcpListener)> {
+        let listener = TcpListener::bind(addr).await?;
+        let relay = Relay {
+            addr: listener.local_addr()?,
+            timeshift,
+            taps: Arc::new(Mutex::new(HashMap::new())),
+        };
+        Ok((relay, listener))
+    }
+
+    pub async fn tap(&self, zone: &str) -> Option<Arc<StreamTap>> {
+        self.taps.lock().await.get(zone).cloned()
+    }
+
+    pub fn url_from(&self, zone: &str, offset: u64) -> String {
+        format!("{}?from={}", self.url(zone), offset)
+    }
+
+    pub fn url(&self, zone: &str) -> String {
+        let host = if self.addr.ip().is_unspecified() {
+            SocketAddr::new([127, 0, 0, 1].into(), self.addr.port())
+        } else {
+            self.addr
+        };
+
+        if zone == DEFAULT_ZONE {
+            format!("http://{}/", host)
+        } else {
+            format!(
+                "http://{}/{}",
+                host,
+                utf8_percent_encode(zone, ZONE_SEGMENT)
+            )
+        }
+    }
+
+    pub async fn publish(&self, zone: &str, tap: Arc<StreamTap>) {
+        if let Some(old) = self.taps.lock().await.insert(zone.to_string(), tap) {
+            old.close();
+        }
+    }
+
+    pub async fn unpublish(&self, zone: &str) {
+        if let Some(old) = self.taps.lock().await.remove(zone) {
+            old.close();
+        }
+    }
+
+    pub async fn serve(self, listener: TcpListener) {
+        loop {
+            let socket = match listener.accept().await {
+                Ok((socket, _)) => socket,
+                Err(err) => {
+                    eprintln!("Relay accept failed: {}", err);
+                    continue;
+                }
+            };
+
+            let relay = self.clone();
+            tokio::spawn(async move {
+                if let Err(err) = relay.serve_listener(socket).await {
+                    eprintln!("Relay listener error: {}", err);
+                }
+            });
+        }
+    }
+
+    async fn serve_listener(&self, mut socket: TcpStream) -> RadioResult<()> {
+        let request = read_request(&mut socket).await?;
+        let mut lines = request.lines();
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().unwrap_or_default().to_string();
+        let path = request_line.next().unwrap_or("/");
+        let wants_meta = lines.any(|line| {
+            line.split_once(':').is_some_and(|(name, value)| {
+                name.trim().eq_ignore_ascii_case("icy-metadata") && value.trim() == "1"
+            })
+        });
+
+        if method != "GET" && method != "HEAD" {
+            socket
+                .write_all(b"HTTP/1.0 405 Method Not Allowed\r\nConnection: close\r\n\r\n")
+                .await?;
+            return Ok(());
+        }
+
+        let (zone, from) = parse_path(path);
+        let tap = self.taps.lock().await.get(&zone).cloned();
+        let audio = tap.as_ref().and_then(|tap| tap.subscribe());
+
+        let (Some(tap), Some(audio)) = (tap, audio) else {
+            socket
+                .write_all(b"HTTP/1.0 404 Not Found\r\nConnection: close\r\n\r\n")
+                .await?;
+            return Ok(());
+        };
+
+        let mut head = String::from("HTTP/1.0 200 OK\r\n");
+        for (name, value) in &tap.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if wants_meta {
+            head.push_str(&format!("icy-metaint: {}\r\n", LISTENER_METAINT));
+        }
+        head.push_str("Cache-Control: no-cache\r\nConnection: close\r\n\r\n");
+        socket.write_all(head.as_bytes()).await?;
+
+        if method == "HEAD" {
+            return Ok(());
+        }
+
+        let mut source = match from {
+            Some(offset) => Source::Shifted {
+                timeshift: tap.timeshift.clone(),
+                next: offset,
+                pending: VecDeque::new(),
+                wakeup: audio,
+            },
+            None => Source::Live(audio),
+        };
+        let mut title = tap.title();
+        drop(tap);
+
+        let mut until_meta = LISTENER_METAINT;
+        let mut sent_title: Option<String> = None;
+
+        while let Some(data) = source.next().await {
+            if !wants_meta {
+                socket.write_all(&data).await?;
+                continue;
+            }
+
+            let mut data = &data[..];
+            while !data.is_empty() {
+                let take = until_meta.min(data.len());
+                socket.write_all(&data[..take]).await?;
+                data = &data[take..];
+                until_meta -= take;
+
+                if until_meta == 0 {
+                    let current = title.borrow_and_update().clone();
+                    if current != sent_title {
+                        socket
+                            .write_all(&icy::encode_title(current.as_deref().unwrap_or_default()))
+                            .await?;
+                        sent_title = current;
+                    } else {
+                        socket.write_all(&[0]).await?;
+                    }
+                    until_meta = LISTENER_METAINT;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The zone addressed by the path of a listener's request and the offset
+/// to serve the stream from.
+fn parse_path(path: &str) -> (String, Option<u64>) {
+    let (zone, query) = path.split_once('?').unwrap_or((path, ""));
+    let zone = percent_decode_str(zone.trim_matches('/')).decode_utf8_lossy();
+    let zone = if zone.is_empty() { DEFAULT_ZONE } else { &zone };
+    let from = query
+        .split('&')
+        .find_map(|param| param.strip_prefix("from="))
+        .and_then(|from| from.parse::<u64>().ok());
+    (zone.to_string(), from)
+}
+
+async fn read_request(socket: &mut TcpStream) -> RadioResult<String> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = socket.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+        if request.len() > MAX_REQUEST_SIZE {
+            return Err("request too large".into());
+        }
+    }
+
+    Ok(String::from_utf8_lossy(&request).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers a request with the response and closes the connection,
+    /// returning the URL.
+    async fn serve(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let _ = read_request(&mut socket).await;
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    fn filled(window: Duration, chunks: &[&'static str]) -> TimeshiftBuffer {
+        let mut buffer = TimeshiftBuffer::new(window);
+        for chunk in chunks {
+            buffer.push(Bytes::from_static(chunk.as_bytes()));
+        }
+        buffer
+    }
+
+    #[test]
+    fn keeps_nothing_without_a_window() {
+        let buffer = filled(Duration::ZERO, &["abc", "def"]);
+        assert_eq!(buffer.offset_at(Duration::ZERO), None);
+        assert_eq!(buffer.chunks_from(0), (Vec::new(), 0));
+        assert_eq!(buffer.buffered(), Duration::ZERO);
+    }
+
+    #[test]
+    fn chunks_follow_the_offset() {
+        let buffer = filled(Duration::from_secs(60), &["abc", "de", "fgh"]);
+        assert_eq!(
+            buffer.chunks_from(3),
+            (
+                vec![Bytes::from_static(b"de"), Bytes::from_static(b"fgh")],
+                8
+            )
+        );
+        assert_eq!(buffer.chunks_from(8), (Vec::new(), 8));
+    }
+
+    #[test]
+    fn seeks_by_arrival_time() {
+        let mut buffer = filled(Duration::from_secs(60), &["abc"]);
+        std::thread::sleep(Duration::from_millis(50));
+        buffer.push(Bytes::from_static(b"de"));
+
+        // Live is the newest chunk, further back than buffered is the oldest.
+        assert_eq!(buffer.offset_at(Duration::ZERO), Some(3));
+        assert_eq!(buffer.offset_at(Duration::from_millis(25)), Some(3));
+        assert_eq!(buffer.offset_at(Duration::from_secs(30)), Some(0));
+        assert!(buffer.buffered() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn drops_chunks_older_than_the_window() {
+        let mut buffer = filled(Duration::from_millis(20), &["abc", "de"]);
+        std::thread::sleep(Duration::from_millis(40));
+        buffer.push(Bytes::from_static(b"fgh"));
+
+        // Offsets keep counting from the start of the stream.
+        assert_eq!(buffer.chunks_from(0), (vec![Bytes::from_static(b"fgh")], 8));
+        assert_eq!(buffer.offset_at(Duration::from_secs(30)), Some(5));
+    }
+
+    #[tokio::test]
+    async fn ends_with_the_upstream() {
+        let url = serve("HTTP/1.0 200 OK\r\nContent-Type: audio/mpeg\r\n\r\naudio").await;
+        let tap = StreamTap::open(&url, Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+        let mut audio = tap.subscribe().unwrap();
+
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while audio.recv().await.is_ok() {}
+        });
+        assert!(closed.await.is_ok());
+        assert!(tap.subscribe().is_none());
+
+        let error = tap.ended().await.unwrap();
+        assert_eq!(error.code, PlayerErrorCode::StreamEnded);
+        assert_eq!(error.url, Some(url));
+    }
+
+    #[tokio::test]
+    async fn zone_names_are_escaped_in_the_path() {
+        let (relay, _listener) = Relay::bind("127.0.0.1:0", Duration::ZERO).await.unwrap();
+        let url = relay.url("Living Room/2");
+        assert!(url.ends_with("/Living%20Room%2F2"));
+
+        let path = &url[url.rfind('/').unwrap()..];
+        assert_eq!(parse_path(path), ("Living Room/2".to_string(), None));
+        assert_eq!(
+            parse_path(&format!("{}?from=42", path)),
+            ("Living Room/2".to_string(), Some(42))
+        );
+        assert_eq!(parse_path("/"), (DEFAULT_ZONE.to_string(), None));
+    }
+}
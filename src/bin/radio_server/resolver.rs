@@ -0,0 +1,317 @@
+use crate::radio_api::HTTP_CLIENT;
+use radioklw::utils::RadioResult;
+use reqwest::header::CONTENT_TYPE;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use url::Url;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PLAYLIST_SIZE: usize = 64 * 1024;
+const MAX_NESTING: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaylistKind {
+    Pls,
+    M3u,
+    Asx,
+    Xspf,
+}
+
+impl PlaylistKind {
+    fn from_url(url: &Url) -> Option<Self> {
+        let path = url.path().to_lowercase();
+        match path.rsplit('.').next() {
+            Some("pls") => Some(PlaylistKind::Pls),
+            Some("m3u") | Some("m3u8") => Some(PlaylistKind::M3u),
+            Some("asx") => Some(PlaylistKind::Asx),
+            Some("xspf") => Some(PlaylistKind::Xspf),
+            _ => None,
+        }
+    }
+
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        match mime.as_str() {
+            "audio/x-scpls" | "audio/scpls" => Some(PlaylistKind::Pls),
+            "audio/x-mpegurl"
+            | "audio/mpegurl"
+            | "application/x-mpegurl"
+            | "application/vnd.apple.mpegurl" => Some(PlaylistKind::M3u),
+            "video/x-ms-asf" | "video/x-ms-asx" | "audio/x-ms-asx" => Some(PlaylistKind::Asx),
+            "application/xspf+xml" => Some(PlaylistKind::Xspf),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves a station URL into an ordered list of candidate stream URLs.
+///
+/// Playlist files are fetched and parsed, redirects are followed and
+/// nested playlists are expanded. URLs that already point at a stream are
+/// returned as they are. If the URL cannot be fetched at all, the URL itself
+/// is returned so that the player still gets a chance to open it.
+pub async fn resolve(url: &str) -> RadioResult<Vec<String>> {
+    let parsed = Url::parse(url)?;
+
+    let mut candidates = match resolve_url(parsed, 0).await {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            eprintln!("Unable to resolve {}: {}", url, err);
+            vec![url.to_string()]
+        }
+    };
+
+    let mut seen = Vec::new();
+    candidates.retain(|candidate| {
+        if seen.contains(candidate) {
+            false
+        } else {
+            seen.push(candidate.clone());
+            true
+        }
+    });
+
+    if candidates.is_empty() {
+        return Err(format!("playlist {} contains no streams", url).into());
+    }
+
+    Ok(candidates)
+}
+
+fn resolve_url(
+    url: Url,
+    depth: u8,
+) -> Pin<Box<dyn Future<Output = RadioResult<Vec<String>>> + Send>> {
+    Box::pin(async move {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Ok(vec![url.to_string()]);
+        }
+
+        let mut response = HTTP_CLIENT
+            .get(url.clone())
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        // Redirects are followed by the client, so relative entries are
+        // resolved against the final location.
+        let final_url = response.url().clone();
+
+        let kind = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(PlaylistKind::from_content_type)
+            .or_else(|| PlaylistKind::from_url(&final_url))
+            .or_else(|| PlaylistKind::from_url(&url));
+
+        let kind = match kind {
+            Some(kind) => kind,
+            None => return Ok(vec![final_url.to_string()]),
+        };
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            body.extend_from_slice(&chunk);
+            if body.len() > MAX_PLAYLIST_SIZE {
+                break;
+            }
+        }
+        let body = String::from_utf8_lossy(&body);
+
+        // HLS playlists are played as they are, the player handles segments.
+        if kind == PlaylistKind::M3u && body.contains("#EXT-X-") {
+            return Ok(vec![final_url.to_string()]);
+        }
+
+        let entries = parse_playlist(kind, &body);
+
+        let mut candidates = Vec::new();
+        for entry in entries {
+            let entry_url = match final_url.join(&entry) {
+                Ok(entry_url) => entry_url,
+                Err(err) => {
+                    eprintln!("Skipping playlist entry {}: {}", entry, err);
+                    continue;
+                }
+            };
+
+            if depth < MAX_NESTING && PlaylistKind::from_url(&entry_url).is_some() {
+                match resolve_url(entry_url.clone(), depth + 1).await {
+                    Ok(nested) => candidates.extend(nested),
+                    Err(err) => eprintln!("Skipping playlist entry {}: {}", entry_url, err),
+                }
+            } else {
+                candidates.push(entry_url.to_string());
+            }
+        }
+
+        Ok(candidates)
+    })
+}
+
+pub fn parse_playlist(kind: PlaylistKind, body: &str) -> Vec<String> {
+    match kind {
+        PlaylistKind::Pls => parse_pls(body),
+        PlaylistKind::M3u => parse_m3u(body),
+        PlaylistKind::Asx => xml_values(body, "ref", Some("href")),
+        PlaylistKind::Xspf => xml_values(body, "location", None),
+    }
+}
+
+fn parse_pls(body: &str) -> Vec<String> {
+    let mut entries: Vec<(u32, String)> = body
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            let index = key
+                .trim()
+                .to_lowercase()
+                .strip_prefix("file")?
+                .parse()
+                .ok()?;
+            Some((index, value.trim().to_string()))
+        })
+        .collect();
+
+    entries.sort_by_key(|(index, _)| *index);
+    entries.into_iter().map(|(_, value)| value).collect()
+}
+
+fn parse_m3u(body: &str) -> Vec<String> {
+    body.lines()
+        .map(|line| line.trim().trim_start_matches('\u{feff}'))
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect()
+}
+
+/// Collects either the given attribute of every `tag` element or, when no
+/// attribute is given, the text content of every `tag` element.
+fn xml_values(body: &str, tag: &str, attribute: Option<&str>) -> Vec<String> {
+    let lower = body.to_ascii_lowercase();
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+    let mut values = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = lower[pos..].find(&open).map(|idx| idx + pos) {
+        let after_name = start + open.len();
+        let Some(end_of_tag) = lower[after_name..].find('>').map(|idx| idx + after_name) else {
+            break;
+        };
+        pos = end_of_tag + 1;
+
+        // Skip longer tag names sharing the same prefix, e.g. <reference>.
+        if lower[after_name..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_alphanumeric())
+        {
+            continue;
+        }
+
+        let value = match attribute {
+            Some(attribute) => attribute_value(
+                &body[after_name..end_of_tag],
+                &lower[after_name..end_of_tag],
+                attribute,
+            ),
+            None => lower[pos..]
+                .find(&close)
+                .map(|idx| body[pos..pos + idx].trim().to_string()),
+        };
+
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            values.push(unescape_xml(&value));
+        }
+    }
+
+    values
+}
+
+fn attribute_value(attrs: &str, lower_attrs: &str, attribute: &str) -> Option<String> {
+    let start = lower_attrs.find(&format!("{}=", attribute))? + attribute.len() + 1;
+    let rest = &attrs[start..];
+    let quote = rest.chars().next()?;
+    if quote == '"' || quote == '\'' {
+        let value = &rest[1..];
+        let end = value.find(quote)?;
+        Some(value[..end].trim().to_string())
+    } else {
+        let end = rest
+            .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .unwrap_or(rest.len());
+        Some(rest[..end].to_string())
+    }
+}
+
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pls_entries_in_file_order() {
+        let body = "[playlist]\nNumberOfEntries=2\nFile2=http://b.example/stream\nTitle1=A\nfile1 = http://a.example/stream \nLength1=-1\n";
+        assert_eq!(
+            parse_playlist(PlaylistKind::Pls, body),
+            ["http://a.example/stream", "http://b.example/stream"]
+        );
+    }
+
+    #[test]
+    fn m3u_skips_comments_and_blank_lines() {
+        let body = "\u{feff}#EXTM3U\n#EXTINF:-1,Station\nhttp://a.example/stream\n\n  http://b.example/stream  \n";
+        assert_eq!(
+            parse_playlist(PlaylistKind::M3u, body),
+            ["http://a.example/stream", "http://b.example/stream"]
+        );
+    }
+
+    #[test]
+    fn asx_ref_hrefs() {
+        let body = r#"<ASX version="3.0"><Entry><Reference href="ignored"/><REF HREF="http://a.example/?a=1&amp;b=2" /><ref href='http://b.example/stream'/></Entry></ASX>"#;
+        assert_eq!(
+            parse_playlist(PlaylistKind::Asx, body),
+            ["http://a.example/?a=1&b=2", "http://b.example/stream"]
+        );
+    }
+
+    #[test]
+    fn xspf_locations() {
+        let body = "<playlist><trackList><track><location> http://a.example/stream </location></track><track><location></location></track></trackList></playlist>";
+        assert_eq!(
+            parse_playlist(PlaylistKind::Xspf, body),
+            ["http://a.example/stream"]
+        );
+    }
+
+    #[test]
+    fn kind_from_url_and_content_type() {
+        let url = Url::parse("http://a.example/listen.M3U8?x=1").unwrap();
+        assert_eq!(PlaylistKind::from_url(&url), Some(PlaylistKind::M3u));
+        let url = Url::parse("http://a.example/stream.mp3").unwrap();
+        assert_eq!(PlaylistKind::from_url(&url), None);
+        assert_eq!(
+            PlaylistKind::from_content_type("audio/x-scpls; charset=utf-8"),
+            Some(PlaylistKind::Pls)
+        );
+        assert_eq!(PlaylistKind::from_content_type("audio/mpeg"), None);
+    }
+}
@@ -0,0 +1,319 @@
+use chrono::{DateTime, Utc};
+use radioklw::utils::RadioResult;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::radio_api::HTTP_CLIENT;
+
+const QUEUE_FILE: &str = "scrobble_queue.json";
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+// ListenBrainz accepts at most 1000 listens per import, keep requests small.
+const MAX_BATCH: usize = 100;
+// Tracks heard for a shorter time (e.g. zapping through stations) are not
+// submitted as listens.
+const MIN_LISTEN_SECS: i64 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AdditionalInfo {
+    media_player: String,
+    submission_client: String,
+    submission_client_version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TrackMetadata {
+    artist_name: String,
+    track_name: String,
+    additional_info: AdditionalInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Listen {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listened_at: Option<i64>,
+    track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, Serialize)]
+struct Submission<'a> {
+    listen_type: &'a str,
+    payload: &'a [Listen],
+}
+
+#[derive(Debug, Clone)]
+struct PlayingTrack {
+    metadata: TrackMetadata,
+    started: DateTime<Utc>,
+}
+
+/// What the background task submits.
+#[derive(Debug)]
+enum Scrobble {
+    PlayingNow(Listen),
+    Listen(Listen),
+}
+
+/// Submits "playing now" notifications and listens derived from ICY track
+/// titles to a ListenBrainz-compatible API. Submissions are sent from a
+/// background task, and listens that cannot be submitted are queued on disk
+/// and retried.
+#[derive(Debug, Clone)]
+pub struct Scrobbler {
+    playing: Arc<Mutex<HashMap<String, PlayingTrack>>>,
+    scrobbles: mpsc::UnboundedSender<Scrobble>,
+}
+
+/// Owns the queue of the listens waiting to be submitted again.
+#[derive(Debug)]
+struct Submitter {
+    base_url: String,
+    token: String,
+    queue: Vec<Listen>,
+}
+
+impl Scrobbler {
+    pub async fn new(base_url: &str, token: &str) -> RadioResult<Self> {
+        let queue = match tokio::fs::read_to_string(QUEUE_FILE).await {
+            Ok(content) if !content.trim().is_empty() => serde_json::from_str(&content)
+                .map_err(|err| eprintln!("Ignoring the listens in {}: {}", QUEUE_FILE, err))
+                .unwrap_or_default(),
+            Ok(_) => Vec::new(),
+            Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(error) => {
+                let err = format!("failed to open the file {}: {:?}", QUEUE_FILE, error);
+                return Err(err.into());
+            }
+        };
+
+        let submitter = Submitter {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            queue,
+        };
+        let (scrobbles, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(submitter.run(receiver));
+
+        Ok(Scrobbler {
+            playing: Arc::new(Mutex::new(HashMap::new())),
+            scrobbles,
+        })
+    }
+
+    /// Called whenever the track title of a zone changes, `None` when the
+    /// zone stopped or the station sent an empty title.
+    pub async fn track_changed(&self, zone: &str, title: Option<&str>) {
+        let now = Utc::now();
+        let metadata = title.and_then(track_metadata);
+
+        let previous = {
+            let mut playing = self.playing.lock().await;
+            let previous = playing.remove(zone);
+            if let Some(metadata) = &metadata {
+                let track = PlayingTrack {
+                    metadata: metadata.clone(),
+                    started: now,
+                };
+                playing.insert(zone.to_string(), track);
+            }
+            previous
+        };
+
+        if let Some(previous) = previous {
+            if (now - previous.started).num_seconds() >= MIN_LISTEN_SECS {
+                self.send(Scrobble::Listen(Listen {
+                    listened_at: Some(previous.started.timestamp()),
+                    track_metadata: previous.metadata,
+                }));
+            }
+        }
+
+        if let Some(metadata) = metadata {
+            self.send(Scrobble::PlayingNow(Listen {
+                listened_at: None,
+                track_metadata: metadata,
+            }));
+        }
+    }
+
+    fn send(&self, scrobble: Scrobble) {
+        if self.scrobbles.send(scrobble).is_err() {
+            eprintln!("Unable to submit listens, the scrobbler has stopped");
+        }
+    }
+}
+
+impl Submitter {
+    async fn run(mut self, mut scrobbles: mpsc::UnboundedReceiver<Scrobble>) {
+        let mut retry = tokio::time::interval_at(Instant::now() + RETRY_INTERVAL, RETRY_INTERVAL);
+        retry.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                scrobble = scrobbles.recv() => match scrobble {
+                    Some(Scrobble::PlayingNow(now_playing)) => {
+                        if let Err(err) = self.submit("playing_now", &[now_playing]).await {
+                            eprintln!("Unable to submit playing now: {}", err);
+                        }
+                    }
+                    Some(Scrobble::Listen(listen)) => self.submit_listen(listen).await,
+                    None => break,
+                },
+                _ = retry.tick() => self.retry_queued().await,
+            }
+        }
+    }
+
+    async fn submit_listen(&mut self, listen: Listen) {
+        match self.submit("single", std::slice::from_ref(&listen)).await {
+            Ok(()) => {}
+            Err(err) if rejected(&err) => {
+                eprintln!("Dropping listen rejected by the scrobbling API: {}", err);
+            }
+            Err(err) => {
+                eprintln!("Unable to submit listen, queued for retry: {}", err);
+                self.queue.push(listen);
+                save_queue(&self.queue).await;
+            }
+        }
+    }
+
+    async fn retry_queued(&mut self) {
+        while !self.queue.is_empty() {
+            let batch = self.queue.len().min(MAX_BATCH);
+            match self.submit("import", &self.queue[..batch]).await {
+                Ok(()) => {}
+                Err(err) if rejected(&err) => {
+                    eprintln!(
+                        "Dropping {} queued listens rejected by the scrobbling API: {}",
+                        batch, err
+                    );
+                }
+                Err(err) => {
+                    let queued = self.queue.len();
+                    eprintln!("Unable to submit {} queued listens: {}", queued, err);
+                    return;
+                }
+            }
+            self.queue.drain(..batch);
+            save_queue(&self.queue).await;
+        }
+    }
+
+    async fn submit(&self, listen_type: &str, payload: &[Listen]) -> reqwest::Result<()> {
+        HTTP_CLIENT
+            .post(format!("{}/1/submit-listens", self.base_url))
+            .header("Authorization", format!("Token {}", self.token))
+            .timeout(SUBMIT_TIMEOUT)
+            .json(&Submission {
+                listen_type,
+                payload,
+            })
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+/// Whether the API refused the listens themselves, so that sending them
+/// again cannot succeed. Rate limiting is retried like network errors.
+fn rejected(err: &reqwest::Error) -> bool {
+    err.status()
+        .is_some_and(|status| status.is_client_error() && status != StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// Splits an ICY title of the form "Artist - Title".
+fn track_metadata(title: &str) -> Option<TrackMetadata> {
+    let (artist, track) = title.split_once(" - ")?;
+    let (artist, track) = (artist.trim(), track.trim());
+    if artist.is_empty() || track.is_empty() {
+        return None;
+    }
+
+    Some(TrackMetadata {
+        artist_name: artist.to_string(),
+        track_name: track.to_string(),
+        additional_info: AdditionalInfo {
+            media_player: "radiokl".to_string(),
+            submission_client: "radiokl".to_string(),
+            submission_client_version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+    })
+}
+
+async fn save_queue(queue: &[Listen]) {
+    let result = match serde_json::to_string(queue) {
+        Ok(json) => tokio::fs::write(QUEUE_FILE, json)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+
+    if let Err(err) = result {
+        eprintln!(
+            "Unable to save the scrobble queue to {}: {}",
+            QUEUE_FILE, err
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_artist_and_title() {
+        let metadata = track_metadata(" Daft Punk - One More Time - Radio Edit ").unwrap();
+        assert_eq!(metadata.artist_name, "Daft Punk");
+        assert_eq!(metadata.track_name, "One More Time - Radio Edit");
+    }
+
+    #[test]
+    fn ignores_titles_without_an_artist() {
+        assert!(track_metadata("Station jingle").is_none());
+        assert!(track_metadata(" - Title").is_none());
+        assert!(track_metadata("Artist - ").is_none());
+        assert!(track_metadata("Artist-Title").is_none());
+    }
+
+    #[test]
+    fn playing_now_has_no_listen_time() {
+        let listen = Listen {
+            listened_at: None,
+            track_metadata: track_metadata("Artist - Title").unwrap(),
+        };
+        let submission = Submission {
+            listen_type: "playing_now",
+            payload: std::slice::from_ref(&listen),
+        };
+        let json = serde_json::to_value(&submission).unwrap();
+
+        assert_eq!(json["listen_type"], "playing_now");
+        assert!(json["payload"][0].get("listened_at").is_none());
+        assert_eq!(
+            json["payload"][0]["track_metadata"]["artist_name"],
+            "Artist"
+        );
+        assert_eq!(
+            json["payload"][0]["track_metadata"]["additional_info"]["media_player"],
+            "radiokl"
+        );
+    }
+
+    #[test]
+    fn listens_have_their_start_time() {
+        let listen = Listen {
+            listened_at: Some(1_700_000_000),
+            track_metadata: track_metadata("Artist - Title").unwrap(),
+        };
+        let json = serde_json::to_value(&listen).unwrap();
+        assert_eq!(json["listened_at"], 1_700_000_000);
+        assert_eq!(json["track_metadata"]["track_name"], "Title");
+    }
+}
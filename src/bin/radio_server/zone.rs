@@ -0,0 +1,587 @@
+use radioklw::{
+    AudioSettings, Event, PlayerError, PlayerErrorCode, PlayerStatus, RadioStation, TimeshiftAction,
+};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::events::Events;
+use crate::history::History;
+use crate::player::{Backend, Player};
+use crate::player_error;
+use crate::radio_api::{Seeker, StationOrder};
+use crate::ranking::Ranking;
+use crate::relay::{Relay, StreamTap};
+use crate::resolver;
+use crate::scrobbler::Scrobbler;
+
+pub use radioklw::DEFAULT_ZONE;
+
+/// Server-wide services shared by all zones.
+#[derive(Debug, Clone)]
+pub struct ZoneContext {
+    pub backend: Backend,
+    pub relay: Relay,
+    pub history: History,
+    pub scrobbler: Option<Scrobbler>,
+    pub events: Events,
+    pub ranking: Ranking,
+    /// Silence after which the zone reports dead air
+    pub silence: Option<Duration>,
+}
+
+/// The URLs tried while starting a station and the last failure.
+struct Attempts {
+    /// The zone's generation when the start began
+    generation: u64,
+    timeshift: Duration,
+    tried: Vec<String>,
+    last_error: Option<PlayerError>,
+}
+
+/// How trying to play a URL ended.
+enum Outcome {
+    Playing,
+    Failed,
+    /// The zone was stopped or played again meanwhile.
+    Abandoned,
+}
+
+/// A named player instance with its own station, volume and output device.
+#[derive(Debug)]
+pub struct Zone {
+    pub name: String,
+    pub player: Player,
+    pub now_playing: PlayerStatus,
+    relay: Relay,
+    history: History,
+    scrobbler: Option<Scrobbler>,
+    events: Events,
+    ranking: Ranking,
+    player_url: Option<String>,
+    relayed: bool,
+    delay: Duration,
+    paused_since: Option<Instant>,
+    titles: Option<watch::Receiver<Option<String>>>,
+    session: Option<(usize, Option<JoinHandle<()>>)>,
+    /// Counts the stops, which abandon the station being started.
+    generation: u64,
+}
+
+impl Zone {
+    pub fn new(name: &str, context: &ZoneContext) -> Self {
+        let mut player = Player::new(name, context.backend.clone(), context.events.clone());
+        player.silence = context.silence;
+
+        Zone {
+            name: name.to_string(),
+            player,
+            now_playing: PlayerStatus::default(),
+            relay: context.relay.clone(),
+            history: context.history.clone(),
+            scrobbler: context.scrobbler.clone(),
+            events: context.events.clone(),
+            ranking: context.ranking.clone(),
+            player_url: None,
+            relayed: false,
+            delay: Duration::ZERO,
+            paused_since: None,
+            titles: None,
+            session: None,
+            generation: 0,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.now_playing.station.is_some()
+    }
+
+    /// How far the playback position is behind the live stream.
+    fn behind_live(&self) -> Duration {
+        let paused = self
+            .paused_since
+            .map(|since| since.elapsed())
+            .unwrap_or_default();
+        (self.delay + paused).min(self.relay.timeshift)
+    }
+
+    pub fn status(&self) -> PlayerStatus {
+        PlayerStatus {
+            zone: self.name.clone(),
+            relay_url: Some(self.relay.url(&self.name))
+                .filter(|_| self.is_playing() && self.relayed),
+            paused: self.paused_since.is_some(),
+            behind_live_secs: self.behind_live().as_secs(),
+            volume: self.player.volume,
+            device: self.player.output_device().map(str::to_string),
+            title: self
+                .titles
+                .as_ref()
+                .and_then(|titles| titles.borrow().clone()),
+            ..self.now_playing.clone()
+        }
+    }
+
+    pub async fn stop(&mut self) -> Result<(), io::Error> {
+        self.generation += 1;
+        if self.is_playing() {
+            self.player.stop().await?;
+            self.relay.unpublish(&self.name).await;
+            self.now_playing = PlayerStatus::default();
+            self.player_url = None;
+            self.relayed = false;
+            self.delay = Duration::ZERO;
+            self.paused_since = None;
+            self.titles = None;
+
+            if let Some((session, watcher)) = self.session.take() {
+                if let Some(watcher) = watcher {
+                    watcher.abort();
+                }
+                self.history.stop(session).await;
+            }
+            if let Some(scrobbler) = &self.scrobbler {
+                scrobbler.track_changed(&self.name, None).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies changed server-wide audio settings unless the playing
+    /// station has its own.
+    pub async fn set_server_audio(&mut self, settings: &AudioSettings) -> Result<(), String> {
+        let overridden = self
+            .now_playing
+            .station
+            .as_ref()
+            .is_some_and(|station| station.audio.is_some());
+        if overridden || self.player.audio == *settings {
+            return Ok(());
+        }
+
+        self.player.audio = settings.clone();
+        self.restart().await
+    }
+
+    /// Pauses the zone or moves its playback position within the
+    /// timeshift buffer of the relayed stream.
+    pub async fn timeshift(&mut self, action: TimeshiftAction) -> Result<(), String> {
+        let tap = match self.relay.tap(&self.name).await {
+            Some(tap) if self.is_playing() && !self.relay.timeshift.is_zero() => tap,
+            _ => return Err("Timeshift is not available for this station".to_string()),
+        };
+
+        let behind = self.behind_live();
+        let delay = match action {
+            TimeshiftAction::Pause => {
+                if self.paused_since.is_none() {
+                    self.player.stop().await.map_err(|err| err.to_string())?;
+                    self.paused_since = Some(Instant::now());
+                }
+                return Ok(());
+            }
+            TimeshiftAction::Resume => behind,
+            TimeshiftAction::Back(secs) => behind + Duration::from_secs(secs.into()),
+            TimeshiftAction::Forward(secs) => {
+                behind.saturating_sub(Duration::from_secs(secs.into()))
+            }
+            TimeshiftAction::Live => Duration::ZERO,
+        };
+
+        let delay = delay.min(tap.buffered());
+        let player_url = match tap.offset_at(delay) {
+            Some(offset) if !delay.is_zero() => self.relay.url_from(&self.name, offset),
+            _ => self.relay.url(&self.name),
+        };
+
+        if self.paused_since.take().is_none() {
+            self.player.stop().await.map_err(|err| err.to_string())?;
+        }
+        self.delay = delay;
+        self.player_url = Some(player_url.clone());
+        if let Err(error) = self.player.play(&[player_url]).await {
+            self.report(error.clone());
+            self.stop().await.map_err(|err| err.to_string())?;
+            return Err(format!("Unable to resume the stream: {}", error));
+        }
+        Ok(())
+    }
+
+    /// Applies a changed server-wide output device unless the zone has
+    /// its own.
+    pub async fn set_server_device(&mut self, device: Option<String>) -> Result<(), String> {
+        if self.player.server_device == device {
+            return Ok(());
+        }
+
+        self.player.server_device = device;
+        if self.player.device.is_some() {
+            return Ok(());
+        }
+        self.restart().await
+    }
+
+    /// Restarts the current stream so that changed player settings take effect.
+    pub async fn restart(&mut self) -> Result<(), String> {
+        let Some(player_url) = self.player_url.clone() else {
+            return Ok(());
+        };
+        // Paused zones pick up the settings when they are resumed.
+        if self.paused_since.is_some() {
+            return Ok(());
+        }
+        // Keep the distance from live rather than the buffer position.
+        let player_url = match self.relay.tap(&self.name).await {
+            Some(tap) if !self.delay.is_zero() => tap
+                .offset_at(self.delay)
+                .map(|offset| self.relay.url_from(&self.name, offset))
+                .unwrap_or(player_url),
+            _ => player_url,
+        };
+        self.player_url = Some(player_url.clone());
+
+        self.player.stop().await.map_err(|err| err.to_string())?;
+        if let Err(error) = self.player.play(&[player_url]).await {
+            self.report(error.clone());
+            self.stop().await.map_err(|err| err.to_string())?;
+            return Err(format!("Unable to restart the stream: {}", error));
+        }
+        Ok(())
+    }
+
+    fn report(&self, error: PlayerError) {
+        self.events.publish(Event::PlayerError {
+            zone: self.name.clone(),
+            error,
+        });
+    }
+
+    /// Points the player at the stream found for the station, through the
+    /// relay if the stream could be tapped.
+    async fn start(
+        &mut self,
+        candidate: &str,
+        tap: Option<Arc<StreamTap>>,
+    ) -> Result<(), PlayerError> {
+        // Streams are read once by the server and relayed to the local
+        // player and other listeners, other streams are played directly.
+        let player_url = match tap {
+            Some(tap) => {
+                self.titles = Some(tap.title());
+                self.relayed = true;
+                self.relay.publish(&self.name, tap).await;
+                self.relay.url(&self.name)
+            }
+            None => {
+                self.titles = None;
+                self.relayed = false;
+                candidate.to_string()
+            }
+        };
+
+        match self.player.play(std::slice::from_ref(&player_url)).await {
+            Ok(_) => {
+                self.player_url = Some(player_url);
+                Ok(())
+            }
+            Err(error) => {
+                eprintln!("Unable to play {}: {}", candidate, error);
+                self.relay.unpublish(&self.name).await;
+                // Report the station's URL rather than the relay's.
+                Err(PlayerError {
+                    url: Some(candidate.to_string()),
+                    ..error
+                })
+            }
+        }
+    }
+}
+
+/// Plays the station in the zone with its own audio settings, or the
+/// server-wide settings if the station has none. The zone is only locked
+/// while a player is started, so that stopping it does not wait for the
+/// station's URLs to be resolved, and a stop abandons the station.
+pub async fn play(
+    zone: &Arc<Mutex<Zone>>,
+    station: &RadioStation,
+    seeker: &Seeker,
+    server_audio: &AudioSettings,
+) -> Result<(), PlayerError> {
+    let mut attempts = {
+        let mut zone = zone.lock().await;
+        zone.stop()
+            .await
+            .map_err(|err| player_error::from_error(&err, None))?;
+        zone.player.audio = station.audio.clone().unwrap_or(server_audio.clone());
+        Attempts {
+            generation: zone.generation,
+            timeshift: zone.relay.timeshift,
+            tried: Vec::new(),
+            last_error: None,
+        }
+    };
+
+    let outcome = play_station(zone, station, seeker, &mut attempts).await;
+    let handle = zone.clone();
+    let mut zone = zone.lock().await;
+    if zone.generation != attempts.generation {
+        return Ok(());
+    }
+
+    match outcome {
+        Outcome::Playing => {
+            if let Some(playing) = zone.now_playing.station.clone() {
+                zone.ranking.click(seeker, &playing).await;
+                let session = zone.history.start(&zone.name, &playing).await;
+                let watcher = zone.titles.clone().map(|titles| {
+                    tokio::spawn(record_tracks(
+                        zone.name.clone(),
+                        zone.history.clone(),
+                        zone.scrobbler.clone(),
+                        session,
+                        titles,
+                    ))
+                });
+                zone.session = Some((session, watcher));
+            }
+            if let Some(tap) = zone.relay.tap(&zone.name).await {
+                tokio::spawn(watch_stream(handle, tap));
+            }
+            Ok(())
+        }
+        Outcome::Abandoned => Ok(()),
+        Outcome::Failed => {
+            let error = attempts.last_error.unwrap_or_else(|| PlayerError {
+                code: PlayerErrorCode::Unknown,
+                message: format!("Unable to play {}", station.name),
+                url: None,
+            });
+            zone.report(error.clone());
+            Err(error)
+        }
+    }
+}
+
+/// Stops the zone when the relayed stream it plays ends by itself, and
+/// reports why.
+async fn watch_stream(zone: Arc<Mutex<Zone>>, tap: Arc<StreamTap>) {
+    let Some(error) = tap.ended().await else {
+        return;
+    };
+
+    let mut zone = zone.lock().await;
+    // The zone may have been stopped and played again meanwhile.
+    let playing = zone.relay.tap(&zone.name).await;
+    if !playing.is_some_and(|playing| Arc::ptr_eq(&playing, &tap)) {
+        return;
+    }
+    if let Err(err) = zone.stop().await {
+        eprintln!("Unable to stop zone {}: {}", zone.name, err);
+    }
+    zone.report(error);
+}
+
+/// Plays the station, falling back to its other URL and then to other
+/// stations with the same name but a different bitrate or codec.
+async fn play_station(
+    zone: &Mutex<Zone>,
+    station: &RadioStation,
+    seeker: &Seeker,
+    attempts: &mut Attempts,
+) -> Outcome {
+    for (url, variant) in own_urls(station) {
+        let status = |stream_url| PlayerStatus {
+            station: Some(station.clone()),
+            stream_url: Some(stream_url),
+            variant: Some(variant.to_string()),
+            ..Default::default()
+        };
+        match try_play(zone, url, attempts, status).await {
+            Outcome::Failed => {}
+            outcome => return outcome,
+        }
+    }
+
+    for alternative in alternatives(station, seeker).await {
+        let variant = format!(
+            "alternative {} kbps {}",
+            alternative.bitrate, alternative.codec
+        );
+        let urls = [alternative.url_resolved.clone(), alternative.url.clone()];
+        let playing = RadioStation {
+            audio: station.audio.clone(),
+            ..alternative
+        };
+
+        for url in urls {
+            let status = |stream_url| PlayerStatus {
+                station: Some(playing.clone()),
+                stream_url: Some(stream_url),
+                variant: Some(variant.clone()),
+                ..Default::default()
+            };
+            match try_play(zone, &url, attempts, status).await {
+                Outcome::Failed => {}
+                outcome => return outcome,
+            }
+        }
+    }
+
+    Outcome::Failed
+}
+
+/// Resolves and opens the URL without locking the zone, then plays the
+/// first stream found unless the zone was stopped meanwhile.
+async fn try_play(
+    zone: &Mutex<Zone>,
+    url: &str,
+    attempts: &mut Attempts,
+    status: impl Fn(String) -> PlayerStatus,
+) -> Outcome {
+    if url.is_empty() || attempts.tried.iter().any(|tried| tried == url) {
+        return Outcome::Failed;
+    }
+    attempts.tried.push(url.to_string());
+
+    let candidates = match resolver::resolve(url).await {
+        Ok(candidates) => candidates,
+        Err(err) => {
+            eprintln!("Unable to resolve {}: {}", url, err);
+            attempts.last_error = Some(player_error::from_error(err.as_ref(), Some(url)));
+            return Outcome::Failed;
+        }
+    };
+
+    for candidate in candidates {
+        let tap = match StreamTap::open(&candidate, attempts.timeshift).await {
+            Ok(tap) => tap,
+            Err(err) => {
+                eprintln!("Unable to open {}: {}", candidate, err);
+                let error = player_error::from_error(err.as_ref(), Some(&candidate));
+                attempts.last_error = Some(error);
+                continue;
+            }
+        };
+
+        let mut zone = zone.lock().await;
+        if zone.generation != attempts.generation {
+            if let Some(tap) = tap {
+                tap.close();
+            }
+            return Outcome::Abandoned;
+        }
+        match zone.start(&candidate, tap).await {
+            Ok(()) => {
+                zone.now_playing = status(candidate);
+                return Outcome::Playing;
+            }
+            Err(error) => attempts.last_error = Some(error),
+        }
+    }
+
+    Outcome::Failed
+}
+
+/// The station's own URLs in the order they are tried, with the variant
+/// reported when one of them plays.
+fn own_urls(station: &RadioStation) -> [(&str, &'static str); 2] {
+    [
+        (station.url_resolved.as_str(), "resolved URL"),
+        (station.url.as_str(), "station URL"),
+    ]
+}
+
+/// Whether a station found by the name is another variant of the station,
+/// rather than the station itself or a copy of its stream.
+fn is_variant(station: &RadioStation, other: &RadioStation) -> bool {
+    other.stationuuid != station.stationuuid
+        && (other.bitrate != station.bitrate || other.codec != station.codec)
+}
+
+async fn alternatives(station: &RadioStation, seeker: &Seeker) -> Vec<RadioStation> {
+    let found = seeker
+        .get_radio_station_search_mapper()
+        .name(station.name.clone())
+        .name_exact(true)
+        .reverse(true)
+        .order(StationOrder::Bitrate)
+        .send()
+        .await;
+
+    match found {
+        Ok(found) => found
+            .into_iter()
+            .filter(|alt| is_variant(station, alt))
+            .collect(),
+        Err(err) => {
+            eprintln!(
+                "Unable to search alternatives for {}: {}",
+                station.name, err
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Logs and scrobbles every new track title of the session until the
+/// stream ends.
+async fn record_tracks(
+    zone: String,
+    history: History,
+    scrobbler: Option<Scrobbler>,
+    session: usize,
+    mut titles: watch::Receiver<Option<String>>,
+) {
+    loop {
+        let title = titles.borrow_and_update().clone();
+        if let Some(scrobbler) = &scrobbler {
+            scrobbler.track_changed(&zone, title.as_deref()).await;
+        }
+        if let Some(title) = title {
+            history.add_track(session, title).await;
+        }
+        if titles.changed().await.is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(uuid: &str, bitrate: u32, codec: &str) -> RadioStation {
+        RadioStation {
+            stationuuid: uuid.to_string(),
+            name: String::from("Jazz FM"),
+            url: format!("http://{}.example/listen.pls", uuid),
+            url_resolved: format!("http://{}.example/stream", uuid),
+            bitrate,
+            codec: codec.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resolved_url_first() {
+        let station = station("a", 128, "MP3");
+        assert_eq!(
+            own_urls(&station),
+            [
+                ("http://a.example/stream", "resolved URL"),
+                ("http://a.example/listen.pls", "station URL"),
+            ]
+        );
+    }
+
+    #[test]
+    fn variants_differ_in_bitrate_or_codec() {
+        let playing = station("a", 128, "MP3");
+        assert!(is_variant(&playing, &station("b", 64, "MP3")));
+        assert!(is_variant(&playing, &station("c", 128, "AAC")));
+        assert!(!is_variant(&playing, &station("d", 128, "MP3")));
+        assert!(!is_variant(&playing, &station("a", 64, "AAC")));
+    }
+}
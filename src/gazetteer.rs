@@ -0,0 +1,68 @@
+//! Coordinates of major cities, for finding the stations near one by name.
+
+const CITIES: &str = include_str!("../data/cities.csv");
+
+#[derive(PartialEq, Clone, Debug)]
+pub struct City {
+    pub name: String,
+    /// ISO 3166-1 alpha-2 country code
+    pub countrycode: String,
+    pub lat: f64,
+    pub long: f64,
+}
+
+pub fn cities() -> impl Iterator<Item = City> {
+    CITIES.lines().skip(1).filter_map(|line| {
+        let mut fields = line.split(',');
+        Some(City {
+            name: fields.next()?.to_string(),
+            countrycode: fields.next()?.to_string(),
+            lat: fields.next()?.parse().ok()?,
+            long: fields.next()?.parse().ok()?,
+        })
+    })
+}
+
+/// Finds a city by its name ignoring case, optionally followed by its
+/// country code, e.g. "valencia, es".
+pub fn find(name: &str) -> Option<City> {
+    let (name, countrycode) = match name.rsplit_once(',') {
+        Some((name, code)) => (name.trim(), Some(code.trim())),
+        None => (name.trim(), None),
+    };
+
+    cities().find(|city| {
+        city.name.eq_ignore_ascii_case(name)
+            && countrycode.is_none_or(|code| city.countrycode.eq_ignore_ascii_case(code))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_cities_ignoring_case() {
+        let city = find("  san francisco ").unwrap();
+        assert_eq!(city.name, "San Francisco");
+        assert_eq!(city.countrycode, "US");
+        assert_eq!((city.lat, city.long), (37.77, -122.42));
+    }
+
+    #[test]
+    fn country_code_narrows_the_search() {
+        assert_eq!(find("Valencia, es").unwrap().countrycode, "ES");
+        assert_eq!(find("Valencia,VE"), None);
+    }
+
+    #[test]
+    fn unknown_cities() {
+        assert_eq!(find("Atlantis"), None);
+        assert_eq!(find(""), None);
+    }
+
+    #[test]
+    fn every_line_is_a_city() {
+        assert_eq!(cities().count(), CITIES.lines().count() - 1);
+    }
+}
@@ -1,9 +1,15 @@
+pub mod gazetteer;
 pub mod utils;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
 use std::sync::Arc;
 
+/// Zone of the requests that do not name one.
+pub const DEFAULT_ZONE: &str = "default";
+
 #[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
 pub struct RadioStation {
     pub changeuuid: String,
@@ -48,24 +54,655 @@ pub struct RadioStation {
     pub geo_lat: Option<f64>,
     pub geo_long: Option<f64>,
     pub has_extended_info: Option<bool>,
+    pub audio: Option<AudioSettings>,
+    /// Kilometers from the place of a nearby search
+    pub distance_km: Option<f64>,
+}
+
+#[derive(PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Default, Debug)]
+pub enum EqPreset {
+    #[default]
+    Flat,
+    Speech,
+    Music,
+    BassBoost,
+}
+
+impl Display for EqPreset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EqPreset::Flat => write!(f, "flat"),
+            EqPreset::Speech => write!(f, "speech"),
+            EqPreset::Music => write!(f, "music"),
+            EqPreset::BassBoost => write!(f, "bass-boost"),
+        }
+    }
+}
+
+impl FromStr for EqPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "flat" => Ok(EqPreset::Flat),
+            "speech" => Ok(EqPreset::Speech),
+            "music" => Ok(EqPreset::Music),
+            "bass-boost" | "bassboost" => Ok(EqPreset::BassBoost),
+            _ => Err(format!(
+                "unknown preset '{}' (flat, speech, music, bass-boost)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct AudioSettings {
+    pub loudnorm: bool,
+    pub eq: EqPreset,
+    pub limiter: bool,
+}
+
+impl Display for AudioSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "loudness normalization: {}, eq: {}, limiter: {}",
+            if self.loudnorm { "on" } else { "off" },
+            self.eq,
+            if self.limiter { "on" } else { "off" }
+        )
+    }
+}
+
+/// Sort keys of radio-browser's station lists.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum StationOrder {
+    Name,
+    Url,
+    Homepage,
+    Favicon,
+    Tags,
+    Country,
+    State,
+    Language,
+    Votes,
+    Codec,
+    Bitrate,
+    Lastcheckok,
+    Lastchecktime,
+    Clicktimestamp,
+    Clickcount,
+    Clicktrend,
+    Changetimestamp,
+    Random,
+}
+
+impl StationOrder {
+    /// Orders two stations by this key in ascending order, the random order
+    /// keeps them as they are.
+    pub fn compare(&self, a: &RadioStation, b: &RadioStation) -> std::cmp::Ordering {
+        match self {
+            StationOrder::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            StationOrder::Url => a.url.cmp(&b.url),
+            StationOrder::Homepage => a.homepage.cmp(&b.homepage),
+            StationOrder::Favicon => a.favicon.cmp(&b.favicon),
+            StationOrder::Tags => a.tags.cmp(&b.tags),
+            StationOrder::Country => a.country.cmp(&b.country),
+            StationOrder::State => a.state.cmp(&b.state),
+            StationOrder::Language => a.language.cmp(&b.language),
+            StationOrder::Votes => a.votes.cmp(&b.votes),
+            StationOrder::Codec => a.codec.cmp(&b.codec),
+            StationOrder::Bitrate => a.bitrate.cmp(&b.bitrate),
+            StationOrder::Lastcheckok => a.lastcheckok.cmp(&b.lastcheckok),
+            StationOrder::Lastchecktime => a.lastchecktime_iso8601.cmp(&b.lastchecktime_iso8601),
+            StationOrder::Clicktimestamp => a.clicktimestamp_iso8601.cmp(&b.clicktimestamp_iso8601),
+            StationOrder::Clickcount => a.clickcount.cmp(&b.clickcount),
+            StationOrder::Clicktrend => a.clicktrend.cmp(&b.clicktrend),
+            StationOrder::Changetimestamp => {
+                a.lastchangetime_iso8601.cmp(&b.lastchangetime_iso8601)
+            }
+            StationOrder::Random => std::cmp::Ordering::Equal,
+        }
+    }
+}
+
+impl Display for StationOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            StationOrder::Name => write!(f, "name"),
+            StationOrder::Url => write!(f, "url"),
+            StationOrder::Homepage => write!(f, "homepage"),
+            StationOrder::Favicon => write!(f, "favicon"),
+            StationOrder::Tags => write!(f, "tags"),
+            StationOrder::Country => write!(f, "country"),
+            StationOrder::State => write!(f, "state"),
+            StationOrder::Language => write!(f, "language"),
+            StationOrder::Votes => write!(f, "votes"),
+            StationOrder::Codec => write!(f, "codec"),
+            StationOrder::Bitrate => write!(f, "bitrate"),
+            StationOrder::Lastcheckok => write!(f, "lastcheckok"),
+            StationOrder::Lastchecktime => write!(f, "lastchecktime"),
+            StationOrder::Clicktimestamp => write!(f, "clicktimestamp"),
+            StationOrder::Clickcount => write!(f, "clickcount"),
+            StationOrder::Clicktrend => write!(f, "clicktrend"),
+            StationOrder::Changetimestamp => write!(f, "changetimestamp"),
+            StationOrder::Random => write!(f, "random"),
+        }
+    }
+}
+
+impl FromStr for StationOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "name" => Ok(StationOrder::Name),
+            "url" => Ok(StationOrder::Url),
+            "homepage" => Ok(StationOrder::Homepage),
+            "favicon" => Ok(StationOrder::Favicon),
+            "tags" => Ok(StationOrder::Tags),
+            "country" => Ok(StationOrder::Country),
+            "state" => Ok(StationOrder::State),
+            "language" => Ok(StationOrder::Language),
+            "votes" => Ok(StationOrder::Votes),
+            "codec" => Ok(StationOrder::Codec),
+            "bitrate" => Ok(StationOrder::Bitrate),
+            "lastcheckok" => Ok(StationOrder::Lastcheckok),
+            "lastchecktime" => Ok(StationOrder::Lastchecktime),
+            "clicktimestamp" => Ok(StationOrder::Clicktimestamp),
+            "clickcount" => Ok(StationOrder::Clickcount),
+            "clicktrend" => Ok(StationOrder::Clicktrend),
+            "changetimestamp" => Ok(StationOrder::Changetimestamp),
+            "random" => Ok(StationOrder::Random),
+            _ => Err(format!(
+                "unknown order '{}' (name, url, homepage, favicon, tags, country, state, \
+                 language, votes, codec, bitrate, lastcheckok, lastchecktime, clicktimestamp, \
+                 clickcount, clicktrend, changetimestamp, random)",
+                s
+            )),
+        }
+    }
 }
 
+/// Filters of a station search, the empty and unset ones are not applied.
 #[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+#[serde(default)]
 pub struct SearchData {
     pub name: String,
     pub country: String,
     pub language: String,
+    /// Stations with a tag containing this
+    pub tag: Option<String>,
+    /// Stations with all of these tags
+    pub tag_list: Vec<String>,
+    /// ISO 3166-1 alpha-2 country code
+    pub countrycode: Option<String>,
+    pub state: Option<String>,
+    pub codec: Option<String>,
+    pub bitrate_min: Option<u32>,
+    pub bitrate_max: Option<u32>,
+    pub is_https: Option<bool>,
+    pub has_geo_info: Option<bool>,
+    /// Leave out the stations that failed the last check
+    pub hidebroken: bool,
+    pub name_exact: bool,
+    pub country_exact: bool,
+    pub language_exact: bool,
+    pub tag_exact: bool,
+    pub state_exact: bool,
+    pub offset: Option<u32>,
+    pub limit: Option<u32>,
+    /// Sort key, the most clicked stations come first if not given
+    pub order: Option<StationOrder>,
+    /// Descending order, the default only without an explicit order
+    pub reverse: Option<bool>,
+    /// Only the stations around this place, the nearest first without an
+    /// explicit order
+    pub near: Option<Near>,
+}
+
+/// A place and the distance around it to find stations in.
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Near {
+    pub lat: f64,
+    pub long: f64,
+    pub radius_km: f64,
+}
+
+impl Near {
+    /// Great-circle distance to the station, `None` if it has no coordinates.
+    pub fn distance_km(&self, station: &RadioStation) -> Option<f64> {
+        const EARTH_RADIUS_KM: f64 = 6371.0;
+
+        let (lat, long) = (station.geo_lat?, station.geo_long?);
+        let (lat1, lat2) = (self.lat.to_radians(), lat.to_radians());
+        let half_dlat = (lat2 - lat1) / 2.0;
+        let half_dlong = (long - self.long).to_radians() / 2.0;
+        let a = half_dlat.sin().powi(2) + lat1.cos() * lat2.cos() * half_dlong.sin().powi(2);
+        Some(2.0 * EARTH_RADIUS_KM * a.sqrt().min(1.0).asin())
+    }
+
+    /// Whether the station is within the radius.
+    pub fn contains(&self, station: &RadioStation) -> bool {
+        self.distance_km(station)
+            .is_some_and(|distance| distance <= self.radius_km)
+    }
+}
+
+/// Lists of values the station filters can take.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum FacetKind {
+    Countries,
+    Languages,
+    Tags,
+    Codecs,
+    States,
+}
+
+impl Display for FacetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FacetKind::Countries => write!(f, "countries"),
+            FacetKind::Languages => write!(f, "languages"),
+            FacetKind::Tags => write!(f, "tags"),
+            FacetKind::Codecs => write!(f, "codecs"),
+            FacetKind::States => write!(f, "states"),
+        }
+    }
+}
+
+/// A filter value and the number of stations having it.
+#[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct Facet {
+    pub name: String,
+    /// ISO country or language code
+    pub code: Option<String>,
+    /// Country of a state
+    pub country: Option<String>,
+    pub stationcount: u32,
+}
+
+impl Display for Facet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:>7}  {}", self.stationcount, self.name)?;
+        if let Some(code) = &self.code {
+            write!(f, " ({})", code)?;
+        }
+        if let Some(country) = &self.country {
+            write!(f, ", {}", country)?;
+        }
+        Ok(())
+    }
+}
+
+/// Contents and use of the server's cache of directory responses.
+#[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub max_bytes: u64,
+    /// Requests answered from the cache since the server started
+    pub hits: u64,
+    /// Requests answered with an expired response while fetching it again
+    pub stale_hits: u64,
+    pub misses: u64,
+    pub oldest: Option<DateTime<Utc>>,
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const MB: f64 = 1024.0 * 1024.0;
+        writeln!(
+            f,
+            "Entries: {} ({:.1} of {:.1} MB)",
+            self.entries,
+            self.bytes as f64 / MB,
+            self.max_bytes as f64 / MB
+        )?;
+        writeln!(f, "Hits:    {} ({} stale)", self.hits, self.stale_hits)?;
+        write!(f, "Misses:  {}", self.misses)?;
+        if let Some(oldest) = self.oldest {
+            write!(f, "\nOldest:  {}", oldest.format("%Y-%m-%d %H:%M UTC"))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct ProbeReport {
+    pub url: String,
+    pub stream_url: Option<String>,
+    pub reachable: bool,
+    pub status: Option<u16>,
+    pub content_type: Option<String>,
+    pub icy_name: Option<String>,
+    pub icy_bitrate: Option<u32>,
+    pub icy_genre: Option<String>,
+    pub time_to_first_byte_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct PlayerStatus {
+    pub zone: String,
+    pub volume: u8,
+    pub device: Option<String>,
+    pub station: Option<RadioStation>,
+    pub stream_url: Option<String>,
+    pub variant: Option<String>,
+    pub relay_url: Option<String>,
+    pub title: Option<String>,
+    pub paused: bool,
+    pub behind_live_secs: u64,
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Default, Debug)]
+pub struct OutputDevice {
+    pub name: String,
+    pub description: Option<String>,
+    pub default: bool,
+}
+
+/// Classification of the reasons a stream could not be played.
+#[derive(PartialEq, Eq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum PlayerErrorCode {
+    NotFound,
+    Forbidden,
+    HttpError,
+    ConnectionFailed,
+    DnsFailure,
+    TlsError,
+    UnsupportedFormat,
+    Timeout,
+    AudioDevice,
+    PlayerMissing,
+    StreamEnded,
+    Unknown,
+}
+
+impl Display for PlayerErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let description = match self {
+            PlayerErrorCode::NotFound => "stream not found",
+            PlayerErrorCode::Forbidden => "access denied",
+            PlayerErrorCode::HttpError => "HTTP error",
+            PlayerErrorCode::ConnectionFailed => "connection failed",
+            PlayerErrorCode::DnsFailure => "host name not resolved",
+            PlayerErrorCode::TlsError => "TLS error",
+            PlayerErrorCode::UnsupportedFormat => "unsupported format",
+            PlayerErrorCode::Timeout => "timed out",
+            PlayerErrorCode::AudioDevice => "audio device error",
+            PlayerErrorCode::PlayerMissing => "player not installed",
+            PlayerErrorCode::StreamEnded => "stream ended",
+            PlayerErrorCode::Unknown => "player error",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerError {
+    pub code: PlayerErrorCode,
+    pub message: String,
+    pub url: Option<String>,
+}
+
+impl Display for PlayerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)?;
+        if let Some(url) = &self.url {
+            write!(f, " ({})", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sent to the clients that subscribed to the server's events.
+#[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
+pub enum Event {
+    PlayerError {
+        zone: String,
+        error: PlayerError,
+    },
+    /// The zone played nothing but silence for `secs` seconds
+    Silence {
+        zone: String,
+        secs: u64,
+    },
+    SilenceEnded {
+        zone: String,
+    },
+    /// Loudest RMS level of the zone's audio in dBFS since the last one
+    Level {
+        zone: String,
+        db: f32,
+    },
+}
+
+impl Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::PlayerError { zone, error } => write!(f, "[{}] {}", zone, error),
+            Event::Silence { zone, secs } => write!(f, "[{}] silent for {} s", zone, secs),
+            Event::SilenceEnded { zone } => write!(f, "[{}] sound is back", zone),
+            Event::Level { zone, db } => write!(f, "[{}] level {:.1} dB", zone, db),
+        }
+    }
+}
+
+/// Moves the playback position within the timeshift buffer of a zone.
+#[derive(PartialEq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum TimeshiftAction {
+    Pause,
+    Resume,
+    Back(u32),
+    Forward(u32),
+    Live,
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
+pub struct TrackEntry {
+    pub title: String,
+    pub started: DateTime<Utc>,
+}
+
+#[derive(PartialEq, Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryEntry {
+    pub zone: String,
+    pub station: String,
+    pub stationuuid: String,
+    pub started: DateTime<Utc>,
+    pub stopped: Option<DateTime<Utc>>,
+    pub tracks: Vec<TrackEntry>,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum Client {
-    Search { search_data: Arc<SearchData> },
-    Play { url: Arc<String> },
-    Stop,
+    Search {
+        search_data: Arc<SearchData>,
+    },
+    Play {
+        zone: Option<String>,
+        station: Arc<RadioStation>,
+    },
+    Stop {
+        zone: Option<String>,
+    },
+    Status {
+        zone: Option<String>,
+    },
+    SetVolume {
+        zone: Option<String>,
+        volume: u8,
+    },
+    SetDevice {
+        zone: Option<String>,
+        device: Option<String>,
+    },
+    ListZones,
+    ListDevices,
+    SetServerDevice {
+        device: Option<String>,
+    },
+    GetAudio,
+    SetAudio {
+        settings: Arc<AudioSettings>,
+    },
+    History {
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    },
+    Probe {
+        urls: Arc<Vec<String>>,
+    },
+    Timeshift {
+        zone: Option<String>,
+        action: TimeshiftAction,
+    },
+    Subscribe,
+    ListFacets {
+        kind: FacetKind,
+        /// Only the values containing this
+        filter: Option<String>,
+        limit: Option<u32>,
+    },
+    Vote {
+        station: Arc<RadioStation>,
+    },
+    CacheStats,
+    ClearCache,
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
 pub enum Server {
-    RadioChList { radio_list: Arc<Vec<RadioStation>> },
+    RadioChList {
+        radio_list: Arc<Vec<RadioStation>>,
+    },
+    ProbeReports {
+        reports: Arc<Vec<ProbeReport>>,
+    },
+    Status {
+        status: Arc<PlayerStatus>,
+    },
+    Zones {
+        zones: Arc<Vec<PlayerStatus>>,
+    },
+    Devices {
+        devices: Arc<Vec<OutputDevice>>,
+        server_device: Option<String>,
+    },
+    Audio {
+        settings: Arc<AudioSettings>,
+    },
+    History {
+        entries: Arc<Vec<HistoryEntry>>,
+    },
+    PlayerError {
+        error: Arc<PlayerError>,
+    },
+    Event {
+        event: Arc<Event>,
+    },
+    Facets {
+        kind: FacetKind,
+        facets: Arc<Vec<Facet>>,
+    },
+    Voted {
+        station: Arc<RadioStation>,
+    },
+    Cache {
+        stats: Arc<CacheStats>,
+    },
     Error(String),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(name: &str, votes: i32) -> RadioStation {
+        RadioStation {
+            name: name.to_string(),
+            votes,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn orders_parse_as_displayed() {
+        let orders = [
+            StationOrder::Name,
+            StationOrder::Votes,
+            StationOrder::Lastchecktime,
+            StationOrder::Clicktrend,
+            StationOrder::Changetimestamp,
+            StationOrder::Random,
+        ];
+        for order in orders {
+            assert_eq!(order.to_string().parse(), Ok(order));
+        }
+        assert_eq!("ClickCount".parse(), Ok(StationOrder::Clickcount));
+        assert!("popularity".parse::<StationOrder>().is_err());
+    }
+
+    #[test]
+    fn compares_by_the_order() {
+        use std::cmp::Ordering;
+
+        let (a, b) = (named("alpha", 10), named("Beta", 2));
+        assert_eq!(StationOrder::Name.compare(&a, &b), Ordering::Less);
+        assert_eq!(StationOrder::Votes.compare(&a, &b), Ordering::Greater);
+        assert_eq!(StationOrder::Random.compare(&a, &b), Ordering::Equal);
+    }
+
+    fn station_at(lat: f64, long: f64) -> RadioStation {
+        RadioStation {
+            geo_lat: Some(lat),
+            geo_long: Some(long),
+            ..Default::default()
+        }
+    }
+
+    const PARIS: Near = Near {
+        lat: 48.86,
+        long: 2.35,
+        radius_km: 400.0,
+    };
+
+    #[test]
+    fn distance_between_cities() {
+        let london = station_at(51.51, -0.13);
+        let distance = PARIS.distance_km(&london).unwrap();
+        assert!((distance - 343.0).abs() < 2.0, "{}", distance);
+
+        let sydney = station_at(-33.87, 151.21);
+        let distance = PARIS.distance_km(&sydney).unwrap();
+        assert!((distance - 16960.0).abs() < 30.0, "{}", distance);
+    }
+
+    #[test]
+    fn distance_to_the_same_place_is_zero() {
+        assert_eq!(PARIS.distance_km(&station_at(48.86, 2.35)), Some(0.0));
+    }
+
+    #[test]
+    fn stations_without_coordinates() {
+        let station = RadioStation {
+            geo_lat: Some(51.51),
+            ..Default::default()
+        };
+        assert_eq!(PARIS.distance_km(&station), None);
+        assert!(!PARIS.contains(&station));
+    }
+
+    #[test]
+    fn contains_stations_within_the_radius() {
+        assert!(PARIS.contains(&station_at(51.51, -0.13)));
+        let near = Near {
+            radius_km: 300.0,
+            ..PARIS
+        };
+        assert!(!near.contains(&station_at(51.51, -0.13)));
+    }
+}
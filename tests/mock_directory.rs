@@ -0,0 +1,141 @@
+//! Runs the server against its mock directory and talks to it like a client.
+
+use radioklw::utils::{receive_one, send_json, RadioResult};
+use radioklw::{Client, RadioStation, SearchData, Server};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::BufReader;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::process::{Child, Command};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// A server started in its own directory, stopped when dropped.
+struct TestServer {
+    _process: Child,
+    dir: PathBuf,
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl TestServer {
+    async fn start(name: &str, extra_args: &[String]) -> RadioResult<Self> {
+        // The server keeps its history, settings and cache in the working
+        // directory.
+        let dir =
+            std::env::temp_dir().join(format!("radio_server_{}_{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+        let process = Command::new(env!("CARGO_BIN_EXE_radio_server"))
+            .args(["--addr", &addr.to_string(), "--mock-directory"])
+            .args(extra_args)
+            .current_dir(&dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let connect = async {
+            loop {
+                match TcpStream::connect(addr).await {
+                    Ok(stream) => return stream,
+                    Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+                }
+            }
+        };
+        let (reader, writer) = tokio::time::timeout(STARTUP_TIMEOUT, connect)
+            .await?
+            .into_split();
+
+        Ok(TestServer {
+            _process: process,
+            dir,
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    async fn request(&mut self, request: Client) -> RadioResult<Server> {
+        send_json(&mut self.writer, &request).await?;
+        tokio::time::timeout(REPLY_TIMEOUT, receive_one(&mut self.reader))
+            .await??
+            .ok_or_else(|| "connection closed".into())
+    }
+
+    async fn search(&mut self, name: &str) -> RadioResult<Vec<RadioStation>> {
+        let search_data = SearchData {
+            name: name.to_string(),
+            ..Default::default()
+        };
+        match self
+            .request(Client::Search {
+                search_data: Arc::new(search_data),
+            })
+            .await?
+        {
+            Server::RadioChList { radio_list } => Ok(radio_list.to_vec()),
+            Server::Error(message) => Err(message.into()),
+            reply => Err(format!("unexpected reply {:?}", reply).into()),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[tokio::test]
+async fn searches_the_mock_stations() {
+    let mut server = TestServer::start("search", &[]).await.unwrap();
+
+    let stations = server.search("tone").await.unwrap();
+    let names: Vec<&str> = stations
+        .iter()
+        .map(|station| station.name.as_str())
+        .collect();
+    assert_eq!(names, ["Mock Tone FM"]);
+
+    let error = server.search("no such station").await.unwrap_err();
+    assert_eq!(error.to_string(), "No radio stations found.");
+}
+
+#[cfg(feature = "native-audio")]
+#[tokio::test]
+async fn plays_a_mock_station_to_a_raw_file() {
+    let dir = std::env::temp_dir().join(format!("radio_server_sink_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("out.raw");
+    let sink = format!("raw:{}", output.display());
+    let mut server = TestServer::start("play", &[String::from("--sink"), sink])
+        .await
+        .unwrap();
+
+    let station = server.search("tone").await.unwrap().remove(0);
+    match server
+        .request(Client::Play {
+            zone: None,
+            station: Arc::new(station),
+        })
+        .await
+        .unwrap()
+    {
+        Server::Status { status } => {
+            assert_eq!(status.station.as_ref().unwrap().name, "Mock Tone FM")
+        }
+        reply => panic!("unexpected reply {:?}", reply),
+    }
+
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    let written = std::fs::metadata(&output)
+        .map(|file| file.len())
+        .unwrap_or(0);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(written > 0, "no audio written to {}", output.display());
+}